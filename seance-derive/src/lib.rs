@@ -0,0 +1,189 @@
+//! Derive macros for seance
+#![warn(missing_docs)]
+
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Field, Fields, LitInt, LitStr, Path, Token, ext::IdentExt, parse_macro_input};
+
+/// Derives typed accessors of session values
+///
+/// Every field of a struct is mapped to a session key named after the field.
+/// A [`SessionKey`] constant named after the field in upper case is added to the struct,
+/// and a `<Struct>Accessors` trait is implemented for `Session`
+/// with `<field>`, `set_<field>` and `remove_<field>` methods.
+/// Getters of raw identifier fields keep the `r#` prefix, e.g. `r#type`.
+/// Two fields mapped to the same key are rejected.
+///
+/// Field attributes:
+///
+/// * `#[session(key = "name")]` - Name of a session key
+/// * `#[session(ttl = 3600)]` - Lifetime of written values in seconds
+/// * `#[session(default)]` - Getter returns `Default::default()` instead of `None`
+/// * `#[session(default = "path::to::function")]` - Getter returns the function result instead of `None`
+///
+/// [`SessionKey`]: https://docs.rs/seance/latest/seance/struct.SessionKey.html
+#[proc_macro_derive(SessionData, attributes(session))]
+pub fn derive_session_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+enum FieldDefault {
+    None,
+    Default,
+    Function(Path),
+}
+
+struct FieldOptions {
+    key: Option<String>,
+    ttl: Option<u64>,
+    default: FieldDefault,
+}
+
+impl FieldOptions {
+    fn parse(field: &Field) -> Result<Self, Error> {
+        let mut options = Self {
+            key: None,
+            ttl: None,
+            default: FieldDefault::None,
+        };
+        for attr in &field.attrs {
+            if !attr.path().is_ident("session") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("key") {
+                    options.key = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("ttl") {
+                    options.ttl = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                } else if meta.path.is_ident("default") {
+                    options.default = if meta.input.peek(Token![=]) {
+                        FieldDefault::Function(meta.value()?.parse::<LitStr>()?.parse()?)
+                    } else {
+                        FieldDefault::Default
+                    };
+                } else {
+                    return Err(meta.error("unsupported session attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "SessionData can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(&input.ident, "SessionData requires named fields"));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "SessionData can not be derived for generic structs",
+        ));
+    }
+
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let accessors = format_ident!("{}Accessors", ident);
+    let mut constants = Vec::new();
+    let mut signatures = Vec::new();
+    let mut methods = Vec::new();
+    let mut keys = HashMap::new();
+    for field in &fields.named {
+        let options = FieldOptions::parse(field)?;
+        let ty = &field.ty;
+        let field_ident = field.ident.as_ref().expect("named field");
+        let name = field_ident.unraw().to_string();
+        let key = options.key.unwrap_or_else(|| name.clone());
+        if let Some(other) = keys.insert(key.clone(), name.clone()) {
+            return Err(Error::new_spanned(
+                field_ident,
+                format!("session key `{key}` is already used by field `{other}`"),
+            ));
+        }
+        let constant = format_ident!("{}", name.to_uppercase());
+        // Raw identifiers are kept, so keywords are valid method names
+        let getter = field_ident.clone();
+        let setter = format_ident!("set_{}", name);
+        let remover = format_ident!("remove_{}", name);
+        let constant_doc = format!("Session key of `{name}`");
+        let getter_doc = format!("Gets a value of `{key}` key");
+        let setter_doc = format!("Sets a value of `{key}` key");
+        let remover_doc = format!("Removes a value of `{key}` key");
+
+        let ttl = options
+            .ttl
+            .map(|ttl| quote!(.ttl(::core::time::Duration::from_secs(#ttl))));
+        constants.push(quote! {
+            #[doc = #constant_doc]
+            #vis const #constant: ::seance::SessionKey<#ty> = ::seance::SessionKey::new(#key) #ttl;
+        });
+
+        let (output, value) = match options.default {
+            FieldDefault::None => (quote!(::core::option::Option<#ty>), quote!(value)),
+            FieldDefault::Default => (quote!(#ty), quote!(value.unwrap_or_default())),
+            FieldDefault::Function(path) => (quote!(#ty), quote!(value.unwrap_or_else(#path))),
+        };
+        let result = quote!(::core::result::Result);
+        let error = quote!(::seance::SessionError);
+        let future = quote!(::core::future::Future);
+        let send = quote!(::core::marker::Send);
+        signatures.push(quote! {
+            #[doc = #getter_doc]
+            fn #getter(&self) -> impl #future<Output = #result<#output, #error>> + #send;
+
+            #[doc = #setter_doc]
+            fn #setter(&self, value: &#ty) -> impl #future<Output = #result<(), #error>> + #send;
+
+            #[doc = #remover_doc]
+            fn #remover(&self) -> impl #future<Output = #result<(), #error>> + #send;
+        });
+        methods.push(quote! {
+            fn #getter(&self) -> impl #future<Output = #result<#output, #error>> + #send {
+                async move {
+                    let value = self.get_typed(&#ident::#constant).await?;
+                    ::core::result::Result::Ok(#value)
+                }
+            }
+
+            fn #setter(&self, value: &#ty) -> impl #future<Output = #result<(), #error>> + #send {
+                async move { self.set_typed(&#ident::#constant, value).await }
+            }
+
+            fn #remover(&self) -> impl #future<Output = #result<(), #error>> + #send {
+                async move { self.remove(#ident::#constant).await }
+            }
+        });
+    }
+
+    let accessors_doc = format!("Typed accessors of [`{ident}`] values");
+    Ok(quote! {
+        impl #ident {
+            #(#constants)*
+        }
+
+        #[doc = #accessors_doc]
+        #[allow(clippy::ptr_arg)]
+        #vis trait #accessors {
+            #(#signatures)*
+        }
+
+        impl<B, C> #accessors for ::seance::Session<B, C>
+        where
+            B: ::seance::backend::SessionBackend,
+            C: ::seance::codec::Codec,
+        {
+            #(#methods)*
+        }
+    })
+}
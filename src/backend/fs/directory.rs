@@ -0,0 +1,278 @@
+use std::{io::ErrorKind as IoErrorKind, path::Path};
+
+use tokio::fs;
+
+use crate::backend::fs::{
+    FilesystemBackendError, RESERVED_PREFIX,
+    modes::FileModes,
+    quota::{Quota, get_file_size, get_size_delta},
+};
+
+const TIME_MARKER: &str = ".__created";
+const ACCESS_MARKER: &str = ".__accessed";
+
+pub(super) async fn get_session_age(session_root: &Path) -> Result<Option<u64>, FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        Ok(Some(TimeMarker::read(session_root, TIME_MARKER).await?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub(super) async fn get_session_access(session_root: &Path) -> Result<Option<u64>, FilesystemBackendError> {
+    if !is_session_root_exists(session_root).await? {
+        return Ok(None);
+    }
+    match TimeMarker::read(session_root, ACCESS_MARKER).await {
+        Err(FilesystemBackendError::TimeMarkerRead(error)) if error.kind() == IoErrorKind::NotFound => {
+            TimeMarker::read(session_root, TIME_MARKER).await.map(Some)
+        }
+        result => result.map(Some),
+    }
+}
+
+pub(super) async fn get_session_times(session_root: &Path) -> Result<Option<(u64, u64)>, FilesystemBackendError> {
+    let Some(created) = get_session_age(session_root).await? else {
+        return Ok(None);
+    };
+    match TimeMarker::read(session_root, ACCESS_MARKER).await {
+        Err(FilesystemBackendError::TimeMarkerRead(error)) if error.kind() == IoErrorKind::NotFound => {
+            Ok(Some((created, created)))
+        }
+        result => result.map(|accessed| Some((created, accessed))),
+    }
+}
+
+pub(super) async fn touch_session(
+    session_root: &Path,
+    timestamp: u64,
+    modes: &FileModes,
+) -> Result<(), FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        TimeMarker::write(session_root, ACCESS_MARKER, timestamp, modes).await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn create_session(
+    session_root: &Path,
+    timestamp: u64,
+    modes: &FileModes,
+) -> Result<bool, FilesystemBackendError> {
+    if let Err(error) = modes.create_dir(session_root).await {
+        return match error.kind() {
+            IoErrorKind::AlreadyExists => Ok(false),
+            _ => Err(FilesystemBackendError::CreateSession(error)),
+        };
+    }
+    TimeMarker::create(session_root, timestamp, modes).await?;
+    Ok(true)
+}
+
+pub(super) async fn remove_session(session_root: &Path, quota: &Quota) -> Result<(), FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        let mut entries = fs::read_dir(session_root)
+            .await
+            .map_err(FilesystemBackendError::RemoveSession)?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(FilesystemBackendError::RemoveSession)?
+        {
+            let size = entry
+                .metadata()
+                .await
+                .map_err(FilesystemBackendError::RemoveSession)?
+                .len();
+            fs::remove_file(entry.path())
+                .await
+                .map_err(FilesystemBackendError::RemoveSession)?;
+            // Markers are never reserved
+            if !entry.file_name().to_string_lossy().starts_with(RESERVED_PREFIX) {
+                quota.release(size as i64);
+            }
+        }
+        fs::remove_dir(session_root)
+            .await
+            .map_err(FilesystemBackendError::RemoveSession)?;
+    }
+    Ok(())
+}
+
+pub(super) async fn read_value(session_root: &Path, key: &str) -> Result<Option<Vec<u8>>, FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        match fs::read(session_root.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(error) => match error.kind() {
+                IoErrorKind::NotFound => Ok(None),
+                _ => Err(FilesystemBackendError::ReadValue(error)),
+            },
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+pub(super) async fn read_all_values(session_root: &Path) -> Result<Vec<(String, Vec<u8>)>, FilesystemBackendError> {
+    let mut result = Vec::new();
+    if is_session_root_exists(session_root).await? {
+        let mut entries = fs::read_dir(session_root)
+            .await
+            .map_err(FilesystemBackendError::ReadValue)?;
+        while let Some(entry) = entries.next_entry().await.map_err(FilesystemBackendError::ReadValue)? {
+            // Keys are always valid UTF-8, so other files do not belong to a session
+            let Ok(key) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Markers and temporary files, keys never start with the prefix
+            if key.starts_with(RESERVED_PREFIX) {
+                continue;
+            }
+            match fs::read(entry.path()).await {
+                Ok(data) => result.push((key, data)),
+                Err(error) => match error.kind() {
+                    IoErrorKind::NotFound => {}
+                    _ => return Err(FilesystemBackendError::ReadValue(error)),
+                },
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub(super) async fn write_value(
+    session_root: &Path,
+    key: &str,
+    value: &[u8],
+    timestamp: u64,
+    modes: &FileModes,
+    quota: &Quota,
+) -> Result<(), FilesystemBackendError> {
+    quota.check_value_size(value.len())?;
+    let path = session_root.join(key);
+    let is_session_exists = is_session_root_exists(session_root).await?;
+    let old_size = if is_session_exists {
+        get_file_size(&path).await.map_err(FilesystemBackendError::WriteValue)?
+    } else {
+        None
+    };
+    if old_size.is_none() && quota.max_keys.is_some() {
+        let keys_count = if is_session_exists {
+            get_keys_count(session_root).await?
+        } else {
+            0
+        };
+        quota.check_new_key(keys_count)?;
+    }
+    let delta = get_size_delta(old_size.unwrap_or(0), value.len() as u64);
+    quota.reserve(delta)?;
+    let result = async {
+        if !is_session_exists {
+            modes
+                .create_dir_all(session_root)
+                .await
+                .map_err(FilesystemBackendError::WriteValue)?;
+            TimeMarker::create(session_root, timestamp, modes).await?;
+        }
+        modes
+            .write_file(&path, value)
+            .await
+            .map_err(FilesystemBackendError::WriteValue)
+    }
+    .await;
+    if result.is_err() {
+        quota.release(delta);
+    }
+    result
+}
+
+pub(super) async fn remove_value(session_root: &Path, key: &str, quota: &Quota) -> Result<(), FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        let path = session_root.join(key);
+        let size = get_file_size(&path)
+            .await
+            .map_err(FilesystemBackendError::RemoveValue)?;
+        if let Err(error) = fs::remove_file(path).await {
+            return match error.kind() {
+                IoErrorKind::NotFound => Ok(()),
+                _ => Err(FilesystemBackendError::RemoveValue(error)),
+            };
+        }
+        quota.release(size.unwrap_or(0) as i64);
+    }
+    Ok(())
+}
+
+pub(super) async fn rewrite_time_marker(
+    session_root: &Path,
+    timestamp: u64,
+    modes: &FileModes,
+) -> Result<(), FilesystemBackendError> {
+    if is_session_root_exists(session_root).await? {
+        TimeMarker::write(session_root, TIME_MARKER, timestamp, modes).await?;
+    }
+    Ok(())
+}
+
+async fn get_keys_count(session_root: &Path) -> Result<usize, FilesystemBackendError> {
+    let mut result = 0;
+    let mut entries = fs::read_dir(session_root)
+        .await
+        .map_err(FilesystemBackendError::WriteValue)?;
+    while let Some(entry) = entries.next_entry().await.map_err(FilesystemBackendError::WriteValue)? {
+        if !entry.file_name().to_string_lossy().starts_with(RESERVED_PREFIX) {
+            result += 1;
+        }
+    }
+    Ok(result)
+}
+
+struct TimeMarker;
+
+impl TimeMarker {
+    async fn create<P: AsRef<Path>>(root: P, timestamp: u64, modes: &FileModes) -> Result<(), FilesystemBackendError> {
+        Self::write(root, TIME_MARKER, timestamp, modes).await
+    }
+
+    async fn write<P: AsRef<Path>>(
+        root: P,
+        name: &str,
+        timestamp: u64,
+        modes: &FileModes,
+    ) -> Result<(), FilesystemBackendError> {
+        let timestamp = format!("{timestamp}");
+        modes
+            .write_file_atomically(&root.as_ref().join(name), timestamp.as_bytes())
+            .await
+            .map_err(FilesystemBackendError::TimeMarkerCreate)?;
+        Ok(())
+    }
+
+    async fn read<P: AsRef<Path>>(root: P, name: &str) -> Result<u64, FilesystemBackendError> {
+        let data = fs::read(root.as_ref().join(name))
+            .await
+            .map_err(FilesystemBackendError::TimeMarkerRead)?;
+        let data = String::from_utf8(data).map_err(FilesystemBackendError::TimeMarkerGetString)?;
+        let timestamp = data
+            .parse::<u64>()
+            .map_err(FilesystemBackendError::TimeMarkerParseValue)?;
+        Ok(timestamp)
+    }
+}
+
+async fn is_session_root_exists<P: AsRef<Path>>(path: P) -> Result<bool, FilesystemBackendError> {
+    let path = path.as_ref();
+    match fs::metadata(&path).await {
+        Ok(meta) => {
+            if meta.is_dir() {
+                Ok(true)
+            } else {
+                Err(FilesystemBackendError::SessionRootOccupied(path.to_path_buf()))
+            }
+        }
+        Err(error) => match error.kind() {
+            IoErrorKind::NotFound => Ok(false),
+            _ => Err(FilesystemBackendError::SessionRootMetadata(error)),
+        },
+    }
+}
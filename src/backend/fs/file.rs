@@ -0,0 +1,378 @@
+use std::{
+    collections::BTreeMap,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::Path,
+};
+
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, BufReader},
+};
+
+use crate::backend::fs::{
+    FilesystemBackendError,
+    modes::FileModes,
+    quota::{Quota, get_file_size, get_size_delta},
+};
+
+pub(super) async fn get_session_age(path: &Path) -> Result<Option<u64>, FilesystemBackendError> {
+    Ok(read_header(path).await?.map(|header| header.created))
+}
+
+pub(super) async fn get_session_access(path: &Path) -> Result<Option<u64>, FilesystemBackendError> {
+    Ok(read_header(path)
+        .await?
+        .map(|header| header.accessed.unwrap_or(header.created)))
+}
+
+pub(super) async fn get_session_times(path: &Path) -> Result<Option<(u64, u64)>, FilesystemBackendError> {
+    Ok(read_header(path)
+        .await?
+        .map(|header| (header.created, header.accessed.unwrap_or(header.created))))
+}
+
+pub(super) async fn touch_session(
+    path: &Path,
+    timestamp: u64,
+    modes: &FileModes,
+    quota: &Quota,
+) -> Result<(), FilesystemBackendError> {
+    let old_size = get_file_size(path)
+        .await
+        .map_err(FilesystemBackendError::TouchSession)?
+        .unwrap_or(0);
+    if let Some(mut session_file) = SessionFile::read(path, FilesystemBackendError::TouchSession).await? {
+        session_file.header.accessed = Some(timestamp);
+        let data = session_file.encode();
+        modes
+            .write_file_atomically(path, &data)
+            .await
+            .map_err(FilesystemBackendError::TouchSession)?;
+        quota.release(-get_size_delta(old_size, data.len() as u64));
+    }
+    Ok(())
+}
+
+pub(super) async fn create_session(
+    path: &Path,
+    timestamp: u64,
+    modes: &FileModes,
+    quota: &Quota,
+) -> Result<bool, FilesystemBackendError> {
+    let data = SessionFile::new(timestamp).encode();
+    let size = data.len() as i64;
+    quota.reserve(size)?;
+    if let Err(error) = modes.create_file(path, &data).await {
+        quota.release(size);
+        return match error.kind() {
+            IoErrorKind::AlreadyExists => Ok(false),
+            _ => Err(FilesystemBackendError::CreateSession(error)),
+        };
+    }
+    Ok(true)
+}
+
+pub(super) async fn remove_session(path: &Path, quota: &Quota) -> Result<(), FilesystemBackendError> {
+    let size = get_file_size(path)
+        .await
+        .map_err(FilesystemBackendError::RemoveSession)?;
+    match fs::remove_file(path).await {
+        Ok(()) => {
+            quota.release(size.unwrap_or(0) as i64);
+            Ok(())
+        }
+        Err(error) => match error.kind() {
+            IoErrorKind::NotFound => Ok(()),
+            _ => Err(FilesystemBackendError::RemoveSession(error)),
+        },
+    }
+}
+
+pub(super) async fn read_value(path: &Path, key: &str) -> Result<Option<Vec<u8>>, FilesystemBackendError> {
+    let session_file = SessionFile::read(path, FilesystemBackendError::ReadValue).await?;
+    Ok(session_file.and_then(|mut session_file| session_file.values.remove(key)))
+}
+
+pub(super) async fn read_values(path: &Path, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, FilesystemBackendError> {
+    let mut values = SessionFile::read(path, FilesystemBackendError::ReadValue)
+        .await?
+        .map(|session_file| session_file.values)
+        .unwrap_or_default();
+    Ok(Vec::from_iter(keys.iter().map(|key| values.remove(*key))))
+}
+
+pub(super) async fn read_all_values(path: &Path) -> Result<Vec<(String, Vec<u8>)>, FilesystemBackendError> {
+    let session_file = SessionFile::read(path, FilesystemBackendError::ReadValue).await?;
+    Ok(session_file
+        .map(|session_file| session_file.values.into_iter().collect())
+        .unwrap_or_default())
+}
+
+pub(super) async fn write_values(
+    path: &Path,
+    values: &[(&str, &[u8])],
+    timestamp: u64,
+    modes: &FileModes,
+    quota: &Quota,
+) -> Result<(), FilesystemBackendError> {
+    for (_, value) in values {
+        quota.check_value_size(value.len())?;
+    }
+    let old_size = get_file_size(path)
+        .await
+        .map_err(FilesystemBackendError::WriteValue)?
+        .unwrap_or(0);
+    let mut session_file = match SessionFile::read(path, FilesystemBackendError::WriteValue).await? {
+        Some(session_file) => session_file,
+        None => SessionFile::new(timestamp),
+    };
+    session_file.header.accessed = Some(timestamp);
+    for (key, value) in values {
+        if !session_file.values.contains_key(*key) {
+            quota.check_new_key(session_file.values.len())?;
+        }
+        session_file.values.insert(String::from(*key), value.to_vec());
+    }
+    let data = session_file.encode();
+    let delta = get_size_delta(old_size, data.len() as u64);
+    quota.reserve(delta)?;
+    modes.write_file_atomically(path, &data).await.map_err(|error| {
+        quota.release(delta);
+        FilesystemBackendError::WriteValue(error)
+    })
+}
+
+/// Removes values from a session file
+///
+/// Access time is kept when timestamp is `None`.
+pub(super) async fn remove_values(
+    path: &Path,
+    keys: &[&str],
+    timestamp: Option<u64>,
+    modes: &FileModes,
+    quota: &Quota,
+) -> Result<(), FilesystemBackendError> {
+    let old_size = get_file_size(path)
+        .await
+        .map_err(FilesystemBackendError::RemoveValue)?
+        .unwrap_or(0);
+    if let Some(mut session_file) = SessionFile::read(path, FilesystemBackendError::RemoveValue).await? {
+        for key in keys {
+            session_file.values.remove(*key);
+        }
+        if timestamp.is_some() {
+            session_file.header.accessed = timestamp;
+        }
+        let data = session_file.encode();
+        modes
+            .write_file_atomically(path, &data)
+            .await
+            .map_err(FilesystemBackendError::RemoveValue)?;
+        quota.release(-get_size_delta(old_size, data.len() as u64));
+    }
+    Ok(())
+}
+
+pub(super) async fn check(path: &Path) -> Result<(), FilesystemBackendError> {
+    SessionFile::read(path, FilesystemBackendError::TimeMarkerRead).await?;
+    Ok(())
+}
+
+pub(super) async fn rewrite_header(path: &Path, created: u64, modes: &FileModes) -> Result<(), FilesystemBackendError> {
+    let data = match fs::read(path).await {
+        Ok(data) => data,
+        Err(error) => {
+            return match error.kind() {
+                IoErrorKind::NotFound => Ok(()),
+                _ => Err(FilesystemBackendError::TimeMarkerRead(error)),
+            };
+        }
+    };
+    let body = match split_line(&data) {
+        Some((_, body)) => body,
+        None if data.is_empty() => &[],
+        // Values could not be told apart from a damaged header, so the file is left as is
+        None => return Err(FilesystemBackendError::SessionFileCorrupted(path.to_path_buf())),
+    };
+    let session_file = SessionFile::decode([format!("{created}\n").as_bytes(), body].concat(), path)?;
+    modes
+        .write_file_atomically(path, &session_file.encode())
+        .await
+        .map_err(FilesystemBackendError::TimeMarkerCreate)
+}
+
+/// A session stored in a single file
+///
+/// The first line contains a creation timestamp optionally followed by a last access timestamp,
+/// each entry is stored as a `<key length> <value length>` line followed by key and value bytes.
+#[derive(Debug, PartialEq)]
+struct SessionFile {
+    header: Header,
+    values: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Header {
+    created: u64,
+    accessed: Option<u64>,
+}
+
+impl SessionFile {
+    fn new(created: u64) -> Self {
+        Self {
+            header: Header {
+                created,
+                accessed: None,
+            },
+            values: BTreeMap::new(),
+        }
+    }
+
+    async fn read<F>(path: &Path, map_err: F) -> Result<Option<Self>, FilesystemBackendError>
+    where
+        F: FnOnce(IoError) -> FilesystemBackendError,
+    {
+        match fs::read(path).await {
+            Ok(data) => Self::decode(data, path).map(Some),
+            Err(error) => match error.kind() {
+                IoErrorKind::NotFound => Ok(None),
+                _ => Err(map_err(error)),
+            },
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut result = match self.header.accessed {
+            Some(accessed) => format!("{} {accessed}\n", self.header.created),
+            None => format!("{}\n", self.header.created),
+        }
+        .into_bytes();
+        for (key, value) in &self.values {
+            result.extend(format!("{} {}\n", key.len(), value.len()).into_bytes());
+            result.extend(key.as_bytes());
+            result.extend(value);
+        }
+        result
+    }
+
+    fn decode(data: Vec<u8>, path: &Path) -> Result<Self, FilesystemBackendError> {
+        let corrupted = || FilesystemBackendError::SessionFileCorrupted(path.to_path_buf());
+        let (header, mut rest) = split_line(&data).ok_or_else(corrupted)?;
+        let mut result = Self {
+            header: parse_header(header.to_vec())?,
+            values: BTreeMap::new(),
+        };
+        while !rest.is_empty() {
+            let (line, tail) = split_line(rest).ok_or_else(corrupted)?;
+            let (key_len, value_len) = std::str::from_utf8(line)
+                .ok()
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(key_len, value_len)| Some((key_len.parse().ok()?, value_len.parse().ok()?)))
+                .ok_or_else(corrupted)?;
+            let total_len = usize::checked_add(key_len, value_len).ok_or_else(corrupted)?;
+            if tail.len() < total_len {
+                return Err(corrupted());
+            }
+            let (key, tail) = tail.split_at(key_len);
+            let (value, tail) = tail.split_at(value_len);
+            let key = String::from_utf8(key.to_vec()).map_err(|_| corrupted())?;
+            result.values.insert(key, value.to_vec());
+            rest = tail;
+        }
+        Ok(result)
+    }
+}
+
+fn split_line(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = data.iter().position(|x| *x == b'\n')?;
+    Some((&data[..pos], &data[pos + 1..]))
+}
+
+async fn read_header(path: &Path) -> Result<Option<Header>, FilesystemBackendError> {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(error) => {
+            return match error.kind() {
+                IoErrorKind::NotFound => Ok(None),
+                _ => Err(FilesystemBackendError::TimeMarkerRead(error)),
+            };
+        }
+    };
+    let mut header = Vec::new();
+    BufReader::new(file)
+        .read_until(b'\n', &mut header)
+        .await
+        .map_err(FilesystemBackendError::TimeMarkerRead)?;
+    if header.pop() != Some(b'\n') {
+        return Err(FilesystemBackendError::SessionFileCorrupted(path.to_path_buf()));
+    }
+    parse_header(header).map(Some)
+}
+
+fn parse_header(header: Vec<u8>) -> Result<Header, FilesystemBackendError> {
+    let header = String::from_utf8(header).map_err(FilesystemBackendError::TimeMarkerGetString)?;
+    let (created, accessed) = match header.split_once(' ') {
+        Some((created, accessed)) => (created, Some(accessed)),
+        None => (header.as_str(), None),
+    };
+    let parse = |value: &str| {
+        value
+            .parse::<u64>()
+            .map_err(FilesystemBackendError::TimeMarkerParseValue)
+    };
+    Ok(Header {
+        created: parse(created)?,
+        accessed: accessed.map(parse).transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_session_file() {
+        let path = Path::new("session-id");
+        let mut session_file = SessionFile::new(100);
+        assert_eq!(session_file.encode(), b"100\n");
+        assert_eq!(SessionFile::decode(session_file.encode(), path).unwrap(), session_file);
+
+        session_file.values.insert(String::from("key"), b"value".to_vec());
+        session_file
+            .values
+            .insert(String::from("multi\nline"), b"\n\n".to_vec());
+        session_file.values.insert(String::from("empty"), Vec::new());
+        assert_eq!(
+            session_file.encode(),
+            b"100\n5 0\nempty3 5\nkeyvalue10 2\nmulti\nline\n\n"
+        );
+        assert_eq!(SessionFile::decode(session_file.encode(), path).unwrap(), session_file);
+
+        session_file.header.accessed = Some(200);
+        assert!(session_file.encode().starts_with(b"100 200\n5 0\n"));
+        assert_eq!(SessionFile::decode(session_file.encode(), path).unwrap(), session_file);
+    }
+
+    #[test]
+    fn decode_corrupted_session_file() {
+        let path = Path::new("session-id");
+        for data in [
+            &b""[..],
+            b"100",
+            b"100\n3 5\nkey",
+            b"100\n3\nkeyvalue",
+            b"100\n3 x\nkeyvalue",
+        ] {
+            assert!(matches!(
+                SessionFile::decode(data.to_vec(), path),
+                Err(FilesystemBackendError::SessionFileCorrupted(_))
+            ));
+        }
+        for data in [&b"abc\n"[..], b"100 abc\n", b"100 \n"] {
+            assert!(matches!(
+                SessionFile::decode(data.to_vec(), path),
+                Err(FilesystemBackendError::TimeMarkerParseValue(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::{self, File},
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::{Path, PathBuf},
+};
+
+use tokio::task::spawn_blocking;
+
+use crate::backend::fs::{FilesystemBackendError, RESERVED_PREFIX, check_session_id, modes::FileModes};
+
+/// Advisory lock for a session shared between processes
+///
+/// Lock files are stored in a reserved directory under the root,
+/// the lock is released when the value is dropped.
+pub(super) struct SessionLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl SessionLock {
+    /// Acquires a lock for reading
+    pub(super) async fn shared(
+        root: &Path,
+        session_id: &str,
+        modes: &FileModes,
+    ) -> Result<Self, FilesystemBackendError> {
+        Self::acquire(root, session_id, modes, false).await
+    }
+
+    /// Acquires a lock for writing
+    pub(super) async fn exclusive(
+        root: &Path,
+        session_id: &str,
+        modes: &FileModes,
+    ) -> Result<Self, FilesystemBackendError> {
+        Self::acquire(root, session_id, modes, true).await
+    }
+
+    async fn acquire(
+        root: &Path,
+        session_id: &str,
+        modes: &FileModes,
+        exclusive: bool,
+    ) -> Result<Self, FilesystemBackendError> {
+        check_session_id(session_id)?;
+        let locks_root = root.join(format!("{RESERVED_PREFIX}locks"));
+        let path = locks_root.join(session_id);
+        let modes = *modes;
+        spawn_blocking(move || {
+            loop {
+                modes.create_dir_all_sync(&locks_root)?;
+                let file = modes
+                    .open_options_sync()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&path)?;
+                if exclusive {
+                    file.lock()?;
+                } else {
+                    file.lock_shared()?;
+                }
+                // The file could be removed by another process while we were waiting for the lock,
+                // so we must start over in order to not hold a lock nobody else can see.
+                if is_lock_file_linked(&file, &path)? {
+                    return Ok(Self { path, _file: file });
+                }
+            }
+        })
+        .await
+        .map_err(IoError::other)
+        .and_then(|result| result)
+        .map_err(FilesystemBackendError::Lock)
+    }
+
+    /// Removes a lock file
+    ///
+    /// Call this only when holding an exclusive lock for a removed session.
+    pub(super) async fn remove(self) -> Result<(), FilesystemBackendError> {
+        if cfg!(unix)
+            && let Err(error) = tokio::fs::remove_file(&self.path).await
+            && error.kind() != IoErrorKind::NotFound
+        {
+            return Err(FilesystemBackendError::Lock(error));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn is_lock_file_linked(file: &File, path: &Path) -> Result<bool, IoError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let expected = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(actual) => Ok(actual.dev() == expected.dev() && actual.ino() == expected.ino()),
+        Err(error) => match error.kind() {
+            IoErrorKind::NotFound => Ok(false),
+            _ => Err(error),
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn is_lock_file_linked(_file: &File, _path: &Path) -> Result<bool, IoError> {
+    // Lock files are never removed on other platforms
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn session_lock() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        let modes = FileModes::default();
+
+        let shared = SessionLock::shared(root, "session-id", &modes).await.unwrap();
+        let other_shared = SessionLock::shared(root, "session-id", &modes).await.unwrap();
+        let other_session = SessionLock::exclusive(root, "other-session-id", &modes).await.unwrap();
+        assert!(
+            timeout(
+                Duration::from_millis(100),
+                SessionLock::exclusive(root, "session-id", &modes)
+            )
+            .await
+            .is_err()
+        );
+        drop(shared);
+        drop(other_shared);
+        drop(other_session);
+
+        let exclusive = SessionLock::exclusive(root, "session-id", &modes).await.unwrap();
+        let root_buf = root.to_path_buf();
+        let waiter = tokio::spawn(async move { SessionLock::shared(&root_buf, "session-id", &modes).await.unwrap() });
+        assert!(
+            timeout(
+                Duration::from_millis(100),
+                SessionLock::shared(root, "session-id", &modes)
+            )
+            .await
+            .is_err()
+        );
+        exclusive.remove().await.unwrap();
+        let shared = waiter.await.unwrap();
+        assert!(shared.path.exists());
+    }
+}
@@ -0,0 +1,1049 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    process,
+    string::FromUtf8Error,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTimeError},
+};
+
+use tokio::fs;
+
+use crate::{
+    backend::{
+        SessionBackend,
+        fs::{lock::SessionLock, modes::FileModes, quota::Quota},
+    },
+    clock::{Clock, SharedClock, SystemClock},
+    utils::{duration_to_secs, now},
+    value::{decode_counter, encode_counter},
+};
+
+pub use self::recovery::{DamagedSession, FsckReport, MarkerRecovery};
+
+mod directory;
+mod file;
+mod lock;
+mod modes;
+mod quota;
+mod recovery;
+
+/// Names starting with this prefix are reserved for internal files
+const RESERVED_PREFIX: &str = ".__";
+
+const DEFAULT_ACCESS_RESOLUTION: u64 = 1;
+
+static UNIQUE_NAME_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Filesystem session backend
+///
+/// Every operation holds an advisory lock for a session,
+/// so it is safe to share `root` between several processes.
+#[derive(Clone)]
+pub struct FilesystemBackend {
+    root: PathBuf,
+    storage: FilesystemStorage,
+    modes: FileModes,
+    marker_recovery: MarkerRecovery,
+    quota: Quota,
+    clock: SharedClock,
+    /// Minimum age of stored access time in seconds before it is updated
+    access_resolution: u64,
+}
+
+impl FilesystemBackend {
+    /// Creates a new backend
+    ///
+    /// # Arguments
+    ///
+    /// * root - Path to sessions directory
+    ///
+    /// Note that you MUST create `root` directory before using this backend,
+    /// use [`FilesystemBackend::builder`] to create it automatically.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            storage: FilesystemStorage::default(),
+            modes: FileModes::default(),
+            marker_recovery: MarkerRecovery::default(),
+            quota: Quota::default(),
+            clock: Arc::new(SystemClock),
+            access_resolution: DEFAULT_ACCESS_RESOLUTION,
+        }
+    }
+
+    /// Creates a new backend with given storage layout
+    ///
+    /// # Arguments
+    ///
+    /// * root - Path to sessions directory
+    /// * storage - How sessions are stored in `root`
+    ///
+    /// Note that you MUST create `root` directory before using this backend,
+    /// use [`FilesystemBackend::builder`] to set other options.
+    pub fn with_storage<P: Into<PathBuf>>(root: P, storage: FilesystemStorage) -> Self {
+        let mut backend = Self::new(root);
+        backend.storage = storage;
+        backend
+    }
+
+    /// Returns a builder to configure a new backend
+    ///
+    /// # Arguments
+    ///
+    /// * root - Path to sessions directory
+    pub fn builder<P: Into<PathBuf>>(root: P) -> FilesystemBackendBuilder {
+        FilesystemBackendBuilder {
+            backend: Self::new(root),
+            create_root: false,
+        }
+    }
+
+    /// Recomputes disk usage by scanning root directory
+    ///
+    /// Disk usage is tracked by a backend and its clones only,
+    /// call this method periodically when several processes write to the same root.
+    pub async fn refresh_usage(&self) -> Result<(), FilesystemBackendError> {
+        self.quota.refresh_usage(&self.root).await
+    }
+
+    /// Checks all sessions in root directory
+    ///
+    /// Returns a report containing sessions with missing or damaged data.
+    /// Lock files left by removed sessions are deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * recovery - What to do with damaged sessions;
+    ///   [`MarkerRecovery::Fail`] and [`MarkerRecovery::UseMtime`] only report them
+    pub async fn fsck(&self, recovery: MarkerRecovery) -> Result<FsckReport, FilesystemBackendError> {
+        let mut report = FsckReport::default();
+        for session_id in self.list_sessions().await? {
+            let path = self.root.join(&session_id);
+            let lock = SessionLock::exclusive(&self.root, &session_id, &self.modes).await?;
+            report.add_checked();
+            let result = match self.storage {
+                FilesystemStorage::Directory => directory::get_session_age(&path).await.map(|_| ()),
+                FilesystemStorage::File => file::check(&path).await,
+            };
+            let error = match result {
+                Ok(()) => continue,
+                Err(error) if error.is_damaged_session() => error,
+                Err(error) => return Err(error),
+            };
+            let repaired = match recovery {
+                MarkerRecovery::Fail | MarkerRecovery::UseMtime => false,
+                MarkerRecovery::Rewrite => match self.rewrite_time_marker(&path).await {
+                    Ok(_) => true,
+                    Err(error) if error.is_damaged_session() => false,
+                    Err(error) => return Err(error),
+                },
+                MarkerRecovery::Quarantine => {
+                    self.quarantine(&session_id, lock).await?;
+                    true
+                }
+            };
+            report.add_damaged(DamagedSession::new(session_id, error, repaired));
+        }
+        // Lock files are removed on other platforms only together with the root
+        if cfg!(unix) {
+            for session_id in self.list_locks().await? {
+                if self.is_session_exists(&session_id).await? {
+                    continue;
+                }
+                let lock = SessionLock::exclusive(&self.root, &session_id, &self.modes).await?;
+                if !self.is_session_exists(&session_id).await? {
+                    lock.remove().await?;
+                    report.add_removed_lock();
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn list_locks(&self) -> Result<Vec<String>, FilesystemBackendError> {
+        let mut result = Vec::new();
+        let mut entries = match fs::read_dir(self.root.join(format!("{RESERVED_PREFIX}locks"))).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                return match error.kind() {
+                    IoErrorKind::NotFound => Ok(result),
+                    _ => Err(FilesystemBackendError::Lock(error)),
+                };
+            }
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(FilesystemBackendError::Lock)? {
+            // Lock files are named after session IDs, which are always valid UTF-8
+            if let Ok(session_id) = entry.file_name().into_string() {
+                result.push(session_id);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn is_session_exists(&self, session_id: &str) -> Result<bool, FilesystemBackendError> {
+        check_session_id(session_id)?;
+        fs::try_exists(self.root.join(session_id))
+            .await
+            .map_err(FilesystemBackendError::Lock)
+    }
+
+    /// Acquires a lock for a session unless it does not exist
+    ///
+    /// Operations which do not create a session use this method,
+    /// so probing unknown IDs does not leave lock files behind.
+    async fn lock_existing(
+        &self,
+        session_id: &str,
+        exclusive: bool,
+    ) -> Result<Option<SessionLock>, FilesystemBackendError> {
+        if !self.is_session_exists(session_id).await? {
+            return Ok(None);
+        }
+        if !exclusive {
+            return SessionLock::shared(&self.root, session_id, &self.modes).await.map(Some);
+        }
+        let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        // Session could be removed while we were waiting for the lock
+        if !self.is_session_exists(session_id).await? {
+            lock.remove().await?;
+            return Ok(None);
+        }
+        Ok(Some(lock))
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>, FilesystemBackendError> {
+        let mut result = Vec::new();
+        let mut entries = match fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                return match error.kind() {
+                    IoErrorKind::NotFound => Ok(result),
+                    _ => Err(FilesystemBackendError::GetSessions(error)),
+                };
+            }
+        };
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(FilesystemBackendError::GetSessions)?
+        {
+            let file_name = entry.file_name();
+            let file_name = match file_name.into_string() {
+                Ok(file_name) => file_name,
+                Err(file_name) => return Err(FilesystemBackendError::GetSessionName(file_name)),
+            };
+            if !file_name.starts_with(RESERVED_PREFIX) {
+                result.push(file_name);
+            }
+        }
+        Ok(result)
+    }
+
+    // Methods below must be called when holding a session lock
+
+    async fn read_value_unlocked(
+        &self,
+        session_id: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        match self.storage {
+            FilesystemStorage::Directory => directory::read_value(&path, key).await,
+            FilesystemStorage::File => file::read_value(&path, key).await,
+        }
+    }
+
+    async fn read_values_unlocked(
+        &self,
+        session_id: &str,
+        keys: &[&str],
+    ) -> Result<Vec<Option<Vec<u8>>>, FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        match self.storage {
+            FilesystemStorage::Directory => {
+                let mut result = Vec::with_capacity(keys.len());
+                for key in keys {
+                    result.push(directory::read_value(&path, key).await?);
+                }
+                Ok(result)
+            }
+            FilesystemStorage::File => file::read_values(&path, keys).await,
+        }
+    }
+
+    async fn write_value_unlocked(
+        &self,
+        session_id: &str,
+        key: &str,
+        value: &[u8],
+    ) -> Result<(), FilesystemBackendError> {
+        self.write_values_unlocked(session_id, &[(key, value)]).await
+    }
+
+    async fn write_values_unlocked(
+        &self,
+        session_id: &str,
+        values: &[(&str, &[u8])],
+    ) -> Result<(), FilesystemBackendError> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let path = self.root.join(session_id);
+        let timestamp = self.get_timestamp()?;
+        self.quota.init_usage(&self.root).await?;
+        match self.storage {
+            FilesystemStorage::Directory => {
+                for (key, value) in values {
+                    directory::write_value(&path, key, value, timestamp, &self.modes, &self.quota).await?;
+                }
+                directory::touch_session(&path, timestamp, &self.modes).await
+            }
+            FilesystemStorage::File => file::write_values(&path, values, timestamp, &self.modes, &self.quota).await,
+        }
+    }
+
+    async fn remove_value_unlocked(&self, session_id: &str, key: &str) -> Result<(), FilesystemBackendError> {
+        self.remove_values_unlocked(session_id, &[key]).await
+    }
+
+    async fn remove_values_unlocked(&self, session_id: &str, keys: &[&str]) -> Result<(), FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        let timestamp = self.get_timestamp()?;
+        match self.storage {
+            FilesystemStorage::Directory => {
+                for key in keys {
+                    directory::remove_value(&path, key, &self.quota).await?;
+                }
+                directory::touch_session(&path, timestamp, &self.modes).await
+            }
+            FilesystemStorage::File => {
+                file::remove_values(&path, keys, Some(timestamp), &self.modes, &self.quota).await
+            }
+        }
+    }
+
+    async fn create_session_unlocked(&self, session_id: &str) -> Result<bool, FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        let timestamp = self.get_timestamp()?;
+        match self.storage {
+            FilesystemStorage::Directory => directory::create_session(&path, timestamp, &self.modes).await,
+            FilesystemStorage::File => {
+                self.quota.init_usage(&self.root).await?;
+                file::create_session(&path, timestamp, &self.modes, &self.quota).await
+            }
+        }
+    }
+
+    /// Updates access time after an operation which did not write to a session
+    ///
+    /// [`FilesystemStorage::File`] rewrites the whole session file, so it requires an exclusive lock.
+    async fn touch_unlocked(&self, session_id: &str) -> Result<(), FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        let timestamp = self.get_timestamp()?;
+        if !self.is_access_outdated(&path, timestamp).await {
+            return Ok(());
+        }
+        match self.storage {
+            FilesystemStorage::Directory => directory::touch_session(&path, timestamp, &self.modes).await,
+            FilesystemStorage::File => file::touch_session(&path, timestamp, &self.modes, &self.quota).await,
+        }
+    }
+
+    /// Updates access time after a read holding a shared lock
+    ///
+    /// Directory storage replaces the marker atomically, so a shared lock is enough.
+    /// File storage releases the shared lock and acquires an exclusive one,
+    /// so concurrent readers never rewrite the file at the same time.
+    async fn touch_after_read(&self, session_id: &str, lock: SessionLock) -> Result<(), FilesystemBackendError> {
+        if self.storage == FilesystemStorage::Directory {
+            return self.touch_unlocked(session_id).await;
+        }
+        let timestamp = self.get_timestamp()?;
+        if !self.is_access_outdated(&self.root.join(session_id), timestamp).await {
+            return Ok(());
+        }
+        drop(lock);
+        let Some(_lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        self.touch_unlocked(session_id).await
+    }
+
+    /// Whether stored access time is older than access resolution
+    async fn is_access_outdated(&self, path: &Path, timestamp: u64) -> bool {
+        if self.access_resolution == 0 {
+            return true;
+        }
+        let accessed = match self.storage {
+            FilesystemStorage::Directory => directory::get_session_access(path).await,
+            FilesystemStorage::File => file::get_session_access(path).await,
+        };
+        match accessed {
+            Ok(Some(accessed)) => timestamp.saturating_sub(accessed) >= self.access_resolution,
+            Ok(None) => false,
+            // Damaged markers are reported by the write
+            Err(_) => true,
+        }
+    }
+
+    fn get_timestamp(&self) -> Result<u64, FilesystemBackendError> {
+        now(self.clock.as_ref()).map_err(FilesystemBackendError::TimeMarkerInitValue)
+    }
+
+    async fn read_session_age(&self, path: &Path) -> Result<Option<u64>, FilesystemBackendError> {
+        match self.storage {
+            FilesystemStorage::Directory => directory::get_session_age(path).await,
+            FilesystemStorage::File => file::get_session_age(path).await,
+        }
+    }
+
+    /// Applies marker recovery policy to a session
+    async fn recover_session_age(
+        &self,
+        session_id: &str,
+        lock: SessionLock,
+    ) -> Result<Option<u64>, FilesystemBackendError> {
+        let path = self.root.join(session_id);
+        let error = match self.read_session_age(&path).await {
+            Err(error) if error.is_damaged_session() => error,
+            result => return result,
+        };
+        log::warn!("Session '{session_id}' is damaged: {error}");
+        match self.marker_recovery {
+            MarkerRecovery::Fail => Err(error),
+            MarkerRecovery::UseMtime => get_mtime(&path).await.map(Some),
+            MarkerRecovery::Rewrite => self.rewrite_time_marker(&path).await.map(Some),
+            MarkerRecovery::Quarantine => {
+                self.quarantine(session_id, lock).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn rewrite_time_marker(&self, path: &Path) -> Result<u64, FilesystemBackendError> {
+        let timestamp = get_mtime(path).await?;
+        match self.storage {
+            FilesystemStorage::Directory => directory::rewrite_time_marker(path, timestamp, &self.modes).await?,
+            FilesystemStorage::File => file::rewrite_header(path, timestamp, &self.modes).await?,
+        }
+        Ok(timestamp)
+    }
+
+    async fn quarantine(&self, session_id: &str, lock: SessionLock) -> Result<(), FilesystemBackendError> {
+        let quarantine_root = self.root.join(format!("{RESERVED_PREFIX}quarantine"));
+        self.modes
+            .create_dir_all(&quarantine_root)
+            .await
+            .map_err(FilesystemBackendError::Quarantine)?;
+        let timestamp = self.get_timestamp()?;
+        let target = quarantine_root.join(get_unique_name(&format!("{session_id}.{timestamp}")));
+        match fs::rename(self.root.join(session_id), target).await {
+            Ok(()) => {}
+            Err(error) => match error.kind() {
+                IoErrorKind::NotFound => {}
+                _ => return Err(FilesystemBackendError::Quarantine(error)),
+            },
+        }
+        lock.remove().await
+    }
+}
+
+/// A builder for filesystem backend
+pub struct FilesystemBackendBuilder {
+    backend: FilesystemBackend,
+    create_root: bool,
+}
+
+impl FilesystemBackendBuilder {
+    /// Sets how sessions are stored in root directory
+    ///
+    /// Default is [`FilesystemStorage::Directory`].
+    pub fn storage(mut self, storage: FilesystemStorage) -> Self {
+        self.backend.storage = storage;
+        self
+    }
+
+    /// Whether to create root directory when it does not exist
+    ///
+    /// Default is `false`.
+    pub fn create_root(mut self, create_root: bool) -> Self {
+        self.create_root = create_root;
+        self
+    }
+
+    /// Sets what to do with sessions having missing or damaged creation time marker
+    ///
+    /// Default is [`MarkerRecovery::Fail`].
+    pub fn marker_recovery(mut self, marker_recovery: MarkerRecovery) -> Self {
+        self.backend.marker_recovery = marker_recovery;
+        self
+    }
+
+    /// Sets a clock for session creation and access time
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.backend.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets how old stored access time must be before it is updated
+    ///
+    /// Reads update access time, which rewrites the whole session file in [`FilesystemStorage::File`],
+    /// so a coarser resolution saves writes at the cost of precision of idle timeouts.
+    /// Zero updates access time on every operation, default is one second.
+    /// Access time is stored in seconds, so resolution is rounded up to a whole second.
+    pub fn access_resolution(mut self, access_resolution: Duration) -> Self {
+        self.backend.access_resolution = duration_to_secs(access_resolution);
+        self
+    }
+
+    /// Sets maximum size of a value in bytes
+    pub fn max_value_size(mut self, max_value_size: usize) -> Self {
+        self.backend.quota.max_value_size = Some(max_value_size);
+        self
+    }
+
+    /// Sets maximum number of keys in a session
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.backend.quota.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Sets maximum size of all files under root directory in bytes
+    ///
+    /// Disk usage is computed on the first write,
+    /// see [`FilesystemBackend::refresh_usage`] for details.
+    pub fn max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.backend.quota.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    /// Sets access mode for created files, e.g. `0o600`
+    ///
+    /// Note that process umask is still applied.
+    #[cfg_attr(nightly, doc(cfg(unix)))]
+    #[cfg(unix)]
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.backend.modes.file = Some(mode);
+        self
+    }
+
+    /// Sets access mode for created directories including root, e.g. `0o700`
+    ///
+    /// Note that process umask is still applied.
+    #[cfg_attr(nightly, doc(cfg(unix)))]
+    #[cfg(unix)]
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.backend.modes.directory = Some(mode);
+        self
+    }
+
+    /// Creates a new backend
+    pub async fn build(self) -> Result<FilesystemBackend, FilesystemBackendError> {
+        let backend = self.backend;
+        if self.create_root {
+            backend
+                .modes
+                .create_dir_all(&backend.root)
+                .await
+                .map_err(FilesystemBackendError::CreateRoot)?;
+        }
+        Ok(backend)
+    }
+}
+
+/// Describes how sessions are stored in a root directory
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FilesystemStorage {
+    /// Each session is a directory containing a file per key and a creation time marker
+    #[default]
+    Directory,
+    /// Each session is a single file containing a creation timestamp and all values
+    ///
+    /// Uses one inode per session, every write rewrites the whole file.
+    File,
+}
+
+impl SessionBackend for FilesystemBackend {
+    type Error = FilesystemBackendError;
+
+    fn get_clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<String>, Self::Error> {
+        self.list_sessions().await
+    }
+
+    async fn get_session_age(&self, session_id: &str) -> Result<Option<u64>, Self::Error> {
+        let path = self.root.join(session_id);
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(None);
+        };
+        match self.read_session_age(&path).await {
+            Err(error) if error.is_damaged_session() && self.marker_recovery != MarkerRecovery::Fail => {}
+            result => return result,
+        }
+        drop(lock);
+        let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        self.recover_session_age(session_id, lock).await
+    }
+
+    async fn get_session_access(&self, session_id: &str) -> Result<Option<u64>, Self::Error> {
+        let path = self.root.join(session_id);
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(None);
+        };
+        let result = match self.storage {
+            FilesystemStorage::Directory => directory::get_session_access(&path).await,
+            FilesystemStorage::File => file::get_session_access(&path).await,
+        };
+        match result {
+            Err(error) if error.is_damaged_session() && self.marker_recovery != MarkerRecovery::Fail => {}
+            result => return result,
+        }
+        drop(lock);
+        let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        self.recover_session_age(session_id, lock).await
+    }
+
+    async fn get_session_times(&self, session_id: &str) -> Result<Option<(u64, u64)>, Self::Error> {
+        let path = self.root.join(session_id);
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(None);
+        };
+        let result = match self.storage {
+            FilesystemStorage::Directory => directory::get_session_times(&path).await,
+            FilesystemStorage::File => file::get_session_times(&path).await,
+        };
+        match result {
+            Err(error) if error.is_damaged_session() && self.marker_recovery != MarkerRecovery::Fail => {}
+            result => return result,
+        }
+        drop(lock);
+        let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        let created = self.recover_session_age(session_id, lock).await?;
+        Ok(created.map(|created| (created, created)))
+    }
+
+    async fn touch_session(&self, session_id: &str) -> Result<(), Self::Error> {
+        let Some(_lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        self.touch_unlocked(session_id).await
+    }
+
+    async fn create_session(&self, session_id: &str) -> Result<bool, Self::Error> {
+        let _lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        self.create_session_unlocked(session_id).await
+    }
+
+    async fn rename_session(&self, session_id: &str, new_session_id: &str) -> Result<bool, Self::Error> {
+        if session_id == new_session_id {
+            return Ok(false);
+        }
+        // Locks are always acquired in the same order to avoid deadlocks
+        let (lock, new_lock) = if session_id < new_session_id {
+            let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+            let new_lock = SessionLock::exclusive(&self.root, new_session_id, &self.modes).await?;
+            (lock, new_lock)
+        } else {
+            let new_lock = SessionLock::exclusive(&self.root, new_session_id, &self.modes).await?;
+            let lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+            (lock, new_lock)
+        };
+        let new_path = self.root.join(new_session_id);
+        if fs::try_exists(&new_path)
+            .await
+            .map_err(FilesystemBackendError::RenameSession)?
+        {
+            return Ok(false);
+        }
+        match fs::rename(self.root.join(session_id), new_path).await {
+            Ok(()) => {
+                lock.remove().await?;
+                Ok(true)
+            }
+            Err(error) => match error.kind() {
+                IoErrorKind::NotFound => {
+                    lock.remove().await?;
+                    new_lock.remove().await?;
+                    Ok(false)
+                }
+                _ => Err(FilesystemBackendError::RenameSession(error)),
+            },
+        }
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<(), Self::Error> {
+        let path = self.root.join(session_id);
+        let Some(lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        match self.storage {
+            FilesystemStorage::Directory => directory::remove_session(&path, &self.quota).await?,
+            FilesystemStorage::File => file::remove_session(&path, &self.quota).await?,
+        }
+        lock.remove().await
+    }
+
+    async fn read_value(&self, session_id: &str, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        check_key(key)?;
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(None);
+        };
+        let result = self.read_value_unlocked(session_id, key).await?;
+        self.touch_after_read(session_id, lock).await?;
+        Ok(result)
+    }
+
+    async fn read_all_values(&self, session_id: &str) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        let path = self.root.join(session_id);
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(Vec::new());
+        };
+        let result = match self.storage {
+            FilesystemStorage::Directory => directory::read_all_values(&path).await?,
+            FilesystemStorage::File => file::read_all_values(&path).await?,
+        };
+        self.touch_after_read(session_id, lock).await?;
+        Ok(result)
+    }
+
+    async fn write_value(&self, session_id: &str, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        check_key(key)?;
+        let _lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        self.write_value_unlocked(session_id, key, value).await
+    }
+
+    async fn remove_value(&self, session_id: &str, key: &str) -> Result<(), Self::Error> {
+        check_key(key)?;
+        let Some(_lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        self.remove_value_unlocked(session_id, key).await
+    }
+
+    async fn read_values(&self, session_id: &str, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        keys.iter().try_for_each(|key| check_key(key))?;
+        let Some(lock) = self.lock_existing(session_id, false).await? else {
+            return Ok(vec![None; keys.len()]);
+        };
+        let result = self.read_values_unlocked(session_id, keys).await?;
+        self.touch_after_read(session_id, lock).await?;
+        Ok(result)
+    }
+
+    async fn write_values(&self, session_id: &str, values: &[(&str, &[u8])]) -> Result<(), Self::Error> {
+        values.iter().try_for_each(|(key, _)| check_key(key))?;
+        let _lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        self.write_values_unlocked(session_id, values).await
+    }
+
+    async fn remove_values(&self, session_id: &str, keys: &[&str]) -> Result<(), Self::Error> {
+        keys.iter().try_for_each(|key| check_key(key))?;
+        let Some(_lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        self.remove_values_unlocked(session_id, keys).await
+    }
+
+    async fn remove_values_if<F>(&self, session_id: &str, predicate: F) -> Result<(), Self::Error>
+    where
+        F: Fn(&str, &[u8]) -> bool + Send + Sync,
+    {
+        let path = self.root.join(session_id);
+        let Some(_lock) = self.lock_existing(session_id, true).await? else {
+            return Ok(());
+        };
+        let values = match self.storage {
+            FilesystemStorage::Directory => directory::read_all_values(&path).await?,
+            FilesystemStorage::File => file::read_all_values(&path).await?,
+        };
+        let keys = Vec::from_iter(
+            values
+                .iter()
+                .filter(|(key, value)| predicate(key, value))
+                .map(|(key, _)| key.as_str()),
+        );
+        if keys.is_empty() {
+            return Ok(());
+        }
+        // Access time is not updated, so idle sessions are still collected
+        match self.storage {
+            FilesystemStorage::Directory => {
+                for key in keys {
+                    directory::remove_value(&path, key, &self.quota).await?;
+                }
+                Ok(())
+            }
+            FilesystemStorage::File => file::remove_values(&path, &keys, None, &self.modes, &self.quota).await,
+        }
+    }
+
+    async fn compare_and_swap(
+        &self,
+        session_id: &str,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool, Self::Error> {
+        check_key(key)?;
+        let _lock = match value {
+            Some(_) => SessionLock::exclusive(&self.root, session_id, &self.modes).await?,
+            None => match self.lock_existing(session_id, true).await? {
+                Some(lock) => lock,
+                // Nothing to remove from a missing session
+                None => return Ok(expected.is_none()),
+            },
+        };
+        let current = self.read_value_unlocked(session_id, key).await?;
+        if current.as_deref() != expected {
+            self.touch_unlocked(session_id).await?;
+            return Ok(false);
+        }
+        match value {
+            Some(value) => self.write_value_unlocked(session_id, key, value).await?,
+            None => self.remove_value_unlocked(session_id, key).await?,
+        }
+        Ok(true)
+    }
+
+    async fn increment_value(&self, session_id: &str, key: &str, delta: i64) -> Result<Option<i64>, Self::Error> {
+        check_key(key)?;
+        let _lock = SessionLock::exclusive(&self.root, session_id, &self.modes).await?;
+        let value = match self.read_value_unlocked(session_id, key).await? {
+            Some(data) => decode_counter(&data),
+            None => Some(0),
+        };
+        let Some(value) = value.and_then(|value| value.checked_add(delta)) else {
+            self.touch_unlocked(session_id).await?;
+            return Ok(None);
+        };
+        self.write_value_unlocked(session_id, key, &encode_counter(value))
+            .await?;
+        Ok(Some(value))
+    }
+}
+
+/// Rejects IDs which could point outside of a session path or to service files
+fn check_session_id(session_id: &str) -> Result<(), FilesystemBackendError> {
+    if session_id.is_empty()
+        || session_id == "."
+        || session_id.contains("..")
+        || session_id.starts_with(RESERVED_PREFIX)
+        || session_id.contains(['/', '\\', '\0'])
+    {
+        return Err(FilesystemBackendError::InvalidSessionId(session_id.to_string()));
+    }
+    Ok(())
+}
+
+/// Rejects keys which could point outside of a session or overwrite its time markers
+///
+/// Keys are file names in [`FilesystemStorage::Directory`],
+/// the same keys are rejected in [`FilesystemStorage::File`] so storages are interchangeable.
+fn check_key(key: &str) -> Result<(), FilesystemBackendError> {
+    if key.is_empty()
+        || key == "."
+        || key == ".."
+        || key.starts_with(RESERVED_PREFIX)
+        || key.contains(['/', '\\', '\0'])
+    {
+        return Err(FilesystemBackendError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+/// Appends a suffix which is unique across processes sharing the root
+fn get_unique_name(name: &str) -> String {
+    let counter = UNIQUE_NAME_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{name}.{}.{counter}", process::id())
+}
+
+async fn get_mtime(path: &Path) -> Result<u64, FilesystemBackendError> {
+    let modified = fs::metadata(path)
+        .await
+        .and_then(|meta| meta.modified())
+        .map_err(FilesystemBackendError::SessionRootMetadata)?;
+    modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .map_err(FilesystemBackendError::TimeMarkerInitValue)
+}
+
+/// An error occurred in filesystem backend
+#[derive(Debug)]
+pub enum FilesystemBackendError {
+    /// Failed to create root directory
+    CreateRoot(IoError),
+    /// Failed to create a session
+    CreateSession(IoError),
+    /// Failed to get sessions list
+    // #[snafu(display("failed to get sessions list: {}", source))]
+    GetSessions(IoError),
+    /// Failed to convert session directory name to string
+    // #[snafu(display("failed to get session name: {:?}", name))]
+    GetSessionName(OsString),
+    /// Key can not be used as a file name
+    InvalidKey(String),
+    /// Session ID can not be used as a file name
+    InvalidSessionId(String),
+    /// Failed to compute disk usage
+    GetUsage(IoError),
+    /// Failed to lock a session
+    Lock(IoError),
+    /// Total size of stored data exceeds given limit
+    QuotaExceeded(u64),
+    /// Failed to move a session to quarantine
+    Quarantine(IoError),
+    /// Failed to read a value
+    // #[snafu(display("failed to read a value: {}", source))]
+    ReadValue(IoError),
+    /// Failed to remove session
+    // #[snafu(display("failed to remove session: {}", source))]
+    RemoveSession(IoError),
+    /// Failed to remove a value
+    // #[snafu(display("failed to remove a value: {}", source))]
+    RemoveValue(IoError),
+    /// Failed to rename session
+    RenameSession(IoError),
+    /// Failed to get session root metadata
+    // #[snafu(display("failed to get session root metadata: {}", source))]
+    SessionRootMetadata(IoError),
+    /// Session directory is occupied by a file
+    // #[snafu(display("session root '{}' is occupied", path.display()))]
+    SessionRootOccupied(PathBuf),
+    /// Session file contains malformed data
+    SessionFileCorrupted(PathBuf),
+    /// Failed to create time marker for a session
+    // #[snafu(display("failed to create time marker: {}", source))]
+    TimeMarkerCreate(IoError),
+    /// Failed to get current time for time marker
+    // #[snafu(display("failed to initialize value for time marker: {}", source))]
+    TimeMarkerInitValue(SystemTimeError),
+    /// Failed to read data from time marker
+    // #[snafu(display("time marker contains non UTF-8 string: {}", source))]
+    TimeMarkerGetString(FromUtf8Error),
+    /// Failed to parse value for a time marker
+    // #[snafu(display("failed to parse time marker value: {}", source))]
+    TimeMarkerParseValue(ParseIntError),
+    /// Failed to read time marker data from a file
+    // #[snafu(display("failed to read time marker data: {}", source))]
+    TimeMarkerRead(IoError),
+    /// Session contains maximum number of keys
+    TooManyKeys(usize),
+    /// Failed to update session access time
+    TouchSession(IoError),
+    /// Value size exceeds given limit
+    ValueTooLarge(usize),
+    /// Failed to write a value
+    // #[snafu(display("failed to write a value: {}", source))]
+    WriteValue(IoError),
+}
+
+impl FilesystemBackendError {
+    /// Whether an error is caused by missing or malformed session data
+    ///
+    /// Other IO errors could be transient, so they never cause a recovery of a session.
+    fn is_damaged_session(&self) -> bool {
+        use self::FilesystemBackendError::*;
+        match self {
+            SessionFileCorrupted(_) | TimeMarkerGetString(_) | TimeMarkerParseValue(_) => true,
+            TimeMarkerRead(err) => err.kind() == IoErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for FilesystemBackendError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::FilesystemBackendError::*;
+        match self {
+            CreateRoot(err) => write!(out, "failed to create root directory: {err}"),
+            CreateSession(err) => write!(out, "failed to create session: {err}"),
+            GetSessions(err) => write!(out, "failed to get sessions list: {err}"),
+            GetSessionName(name) => write!(out, "failed to get session name: {name:?}"),
+            GetUsage(err) => write!(out, "failed to compute disk usage: {err}"),
+            InvalidKey(key) => write!(out, "invalid key: {key:?}"),
+            InvalidSessionId(session_id) => write!(out, "invalid session ID: {session_id:?}"),
+            Lock(err) => write!(out, "failed to lock a session: {err}"),
+            QuotaExceeded(limit) => write!(out, "total size of stored data exceeds {limit} bytes"),
+            Quarantine(err) => write!(out, "failed to quarantine a session: {err}"),
+            ReadValue(err) => write!(out, "failed to read a value: {err}"),
+            RemoveSession(err) => write!(out, "failed to remove session: {err}"),
+            RemoveValue(err) => write!(out, "failed to remove a value: {err}"),
+            RenameSession(err) => write!(out, "failed to rename session: {err}"),
+            SessionRootMetadata(err) => {
+                write!(out, "failed to get session root metadata: {err}")
+            }
+            SessionRootOccupied(path) => {
+                write!(out, "session root '{}' is occupied", path.display())
+            }
+            SessionFileCorrupted(path) => {
+                write!(out, "session file '{}' is corrupted", path.display())
+            }
+            TimeMarkerCreate(err) => write!(out, "failed to create time marker: {err}"),
+            TimeMarkerInitValue(err) => {
+                write!(out, "failed to initialize value for time marker: {err}")
+            }
+            TimeMarkerGetString(err) => {
+                write!(out, "time marker contains non UTF-8 string: {err}")
+            }
+            TimeMarkerParseValue(err) => {
+                write!(out, "failed to parse time marker value: {err}")
+            }
+            TimeMarkerRead(err) => write!(out, "failed to read time marker data: {err}"),
+            TooManyKeys(limit) => write!(out, "session can not contain more than {limit} keys"),
+            TouchSession(err) => write!(out, "failed to update session access time: {err}"),
+            ValueTooLarge(limit) => write!(out, "value size exceeds {limit} bytes"),
+            WriteValue(err) => write!(out, "failed to write a value: {err}"),
+        }
+    }
+}
+
+impl Error for FilesystemBackendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::FilesystemBackendError::*;
+        Some(match self {
+            CreateRoot(err) => err,
+            CreateSession(err) => err,
+            GetSessions(err) => err,
+            GetSessionName(_) => return None,
+            GetUsage(err) => err,
+            InvalidKey(_) => return None,
+            InvalidSessionId(_) => return None,
+            Lock(err) => err,
+            QuotaExceeded(_) => return None,
+            Quarantine(err) => err,
+            ReadValue(err) => err,
+            RemoveSession(err) => err,
+            RemoveValue(err) => err,
+            RenameSession(err) => err,
+            SessionRootMetadata(err) => err,
+            SessionRootOccupied(_) => return None,
+            SessionFileCorrupted(_) => return None,
+            TimeMarkerCreate(err) => err,
+            TimeMarkerInitValue(err) => err,
+            TimeMarkerGetString(err) => err,
+            TimeMarkerParseValue(err) => err,
+            TimeMarkerRead(err) => err,
+            TooManyKeys(_) => return None,
+            TouchSession(err) => err,
+            ValueTooLarge(_) => return None,
+            WriteValue(err) => err,
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    fs::OpenOptions as StdOpenOptions,
+    io::Error as IoError,
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs::{self, DirBuilder, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::backend::fs::{RESERVED_PREFIX, get_unique_name};
+
+/// Access modes for created files and directories
+///
+/// `None` means that the default mode is used.
+/// Note that process umask is still applied to given modes.
+#[cfg_attr(not(unix), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct FileModes {
+    pub(super) file: Option<u32>,
+    pub(super) directory: Option<u32>,
+}
+
+impl FileModes {
+    pub(super) async fn create_dir_all(&self, path: &Path) -> Result<(), IoError> {
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.directory {
+            builder.mode(mode);
+        }
+        builder.create(path).await
+    }
+
+    pub(super) async fn create_dir(&self, path: &Path) -> Result<(), IoError> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        if let Some(mode) = self.directory {
+            builder.mode(mode);
+        }
+        builder.create(path).await
+    }
+
+    pub(super) fn create_dir_all_sync(&self, path: &Path) -> Result<(), IoError> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.directory {
+            use std::os::unix::fs::DirBuilderExt;
+
+            builder.mode(mode);
+        }
+        builder.create(path)
+    }
+
+    pub(super) async fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), IoError> {
+        let mut options = OpenOptions::new();
+        options.create(true).truncate(true).write(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.file {
+            options.mode(mode);
+        }
+        let mut file = options.open(path).await?;
+        file.write_all(data).await?;
+        file.flush().await
+    }
+
+    /// Writes data to a new file, fails if file already exists
+    pub(super) async fn create_file(&self, path: &Path, data: &[u8]) -> Result<(), IoError> {
+        let mut options = OpenOptions::new();
+        options.create_new(true).write(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.file {
+            options.mode(mode);
+        }
+        let mut file = options.open(path).await?;
+        file.write_all(data).await?;
+        file.flush().await
+    }
+
+    /// Replaces file contents, so readers never see partially written data
+    pub(super) async fn write_file_atomically(&self, path: &Path, data: &[u8]) -> Result<(), IoError> {
+        let temp_path = get_temp_path(path);
+        if let Err(error) = self.write_file(&temp_path, data).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(error);
+        }
+        if let Err(error) = fs::rename(&temp_path, path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub(super) fn open_options_sync(&self) -> StdOpenOptions {
+        let mut options = StdOpenOptions::new();
+        #[cfg(unix)]
+        if let Some(mode) = self.file {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(mode);
+        }
+        options
+    }
+}
+
+fn get_temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(get_unique_name(&format!("{RESERVED_PREFIX}tmp")))
+}
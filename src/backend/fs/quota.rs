@@ -0,0 +1,119 @@
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tokio::fs;
+
+use crate::backend::fs::{FilesystemBackendError, RESERVED_PREFIX};
+
+/// Size limits for stored data
+///
+/// Disk usage is computed by scanning root directory on the first write
+/// and then tracked by all clones of a backend.
+/// Service files such as markers, locks and quarantined sessions are not counted.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Quota {
+    pub(super) max_value_size: Option<usize>,
+    pub(super) max_keys: Option<usize>,
+    pub(super) max_total_bytes: Option<u64>,
+    usage: Arc<Mutex<Option<u64>>>,
+}
+
+impl Quota {
+    pub(super) fn check_value_size(&self, size: usize) -> Result<(), FilesystemBackendError> {
+        match self.max_value_size {
+            Some(limit) if size > limit => Err(FilesystemBackendError::ValueTooLarge(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether a new key can be added to a session containing given number of keys
+    pub(super) fn check_new_key(&self, keys_count: usize) -> Result<(), FilesystemBackendError> {
+        match self.max_keys {
+            Some(limit) if keys_count >= limit => Err(FilesystemBackendError::TooManyKeys(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Computes disk usage if it is unknown
+    pub(super) async fn init_usage(&self, root: &Path) -> Result<(), FilesystemBackendError> {
+        if self.max_total_bytes.is_some() && self.usage.lock().unwrap().is_none() {
+            self.refresh_usage(root).await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn refresh_usage(&self, root: &Path) -> Result<(), FilesystemBackendError> {
+        let usage = get_disk_usage(root).await.map_err(FilesystemBackendError::GetUsage)?;
+        *self.usage.lock().unwrap() = Some(usage);
+        Ok(())
+    }
+
+    /// Adds a size of data to be written to disk usage
+    ///
+    /// Returns an error when total size limit is exceeded,
+    /// call [`Quota::release`] if data was not written.
+    pub(super) fn reserve(&self, delta: i64) -> Result<(), FilesystemBackendError> {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(limit) = self.max_total_bytes
+            && let Some(usage) = usage.as_mut()
+        {
+            let new_usage = usage.saturating_add_signed(delta);
+            if delta > 0 && new_usage > limit {
+                return Err(FilesystemBackendError::QuotaExceeded(limit));
+            }
+            *usage = new_usage;
+        }
+        Ok(())
+    }
+
+    /// Reverts a reservation
+    pub(super) fn release(&self, delta: i64) {
+        if let Some(usage) = self.usage.lock().unwrap().as_mut() {
+            *usage = usage.saturating_add_signed(-delta);
+        }
+    }
+}
+
+pub(super) fn get_size_delta(old_size: u64, new_size: u64) -> i64 {
+    new_size as i64 - old_size as i64
+}
+
+/// Returns a size of file or `None` if it does not exist
+pub(super) async fn get_file_size(path: &Path) -> Result<Option<u64>, IoError> {
+    match fs::metadata(path).await {
+        Ok(meta) => Ok(Some(meta.len())),
+        Err(error) => match error.kind() {
+            IoErrorKind::NotFound => Ok(None),
+            _ => Err(error),
+        },
+    }
+}
+
+async fn get_disk_usage(root: &Path) -> Result<u64, IoError> {
+    let mut result = 0;
+    let mut directories: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let mut entries = match fs::read_dir(&directory).await {
+            Ok(entries) => entries,
+            Err(error) => match error.kind() {
+                IoErrorKind::NotFound => continue,
+                _ => return Err(error),
+            },
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with(RESERVED_PREFIX) {
+                continue;
+            }
+            let meta = entry.metadata().await?;
+            if meta.is_dir() {
+                directories.push(entry.path());
+            } else {
+                result += meta.len();
+            }
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,89 @@
+use crate::backend::fs::FilesystemBackendError;
+
+/// Describes what to do with a session when its creation time marker is missing or damaged
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MarkerRecovery {
+    /// Return an error
+    #[default]
+    Fail,
+    /// Use modification time of a session as its creation time
+    UseMtime,
+    /// Use modification time of a session as its creation time and write a repaired marker
+    Rewrite,
+    /// Move a session to the quarantine directory and treat it as missing
+    ///
+    /// Quarantined sessions are stored in `.__quarantine` directory under the root.
+    Quarantine,
+}
+
+/// A result of the filesystem check
+///
+/// See [`FilesystemBackend::fsck`](super::FilesystemBackend::fsck)
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    checked: usize,
+    damaged: Vec<DamagedSession>,
+    removed_locks: usize,
+}
+
+impl FsckReport {
+    pub(super) fn add_checked(&mut self) {
+        self.checked += 1;
+    }
+
+    pub(super) fn add_damaged(&mut self, session: DamagedSession) {
+        self.damaged.push(session);
+    }
+
+    pub(super) fn add_removed_lock(&mut self) {
+        self.removed_locks += 1;
+    }
+
+    /// Returns a number of checked sessions
+    pub fn checked(&self) -> usize {
+        self.checked
+    }
+
+    /// Returns a list of damaged sessions
+    pub fn damaged(&self) -> &[DamagedSession] {
+        &self.damaged
+    }
+
+    /// Returns a number of removed lock files which did not belong to any session
+    pub fn removed_locks(&self) -> usize {
+        self.removed_locks
+    }
+}
+
+/// A session found by the filesystem check
+#[derive(Debug)]
+pub struct DamagedSession {
+    session_id: String,
+    error: FilesystemBackendError,
+    repaired: bool,
+}
+
+impl DamagedSession {
+    pub(super) fn new(session_id: String, error: FilesystemBackendError, repaired: bool) -> Self {
+        Self {
+            session_id,
+            error,
+            repaired,
+        }
+    }
+
+    /// Returns ID of the session
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Returns an error occurred when reading the session
+    pub fn error(&self) -> &FilesystemBackendError {
+        &self.error
+    }
+
+    /// Whether the session was repaired or quarantined
+    pub fn is_repaired(&self) -> bool {
+        self.repaired
+    }
+}
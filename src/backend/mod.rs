@@ -1,4 +1,9 @@
-use std::{error::Error, future::Future};
+use std::{error::Error, future::Future, sync::Arc};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    value::{decode_counter, encode_counter},
+};
 
 /// Filesystem backend
 #[cfg_attr(nightly, doc(cfg(feature = "fs-backend")))]
@@ -11,12 +16,25 @@ pub mod fs;
 pub mod redis;
 
 /// A session backend interface
-pub trait SessionBackend {
+///
+/// Backend is shared between all sessions, so methods take `&self`
+/// and implementations must allow concurrent calls.
+pub trait SessionBackend: Send + Sync {
     /// An error occurred in backend
     type Error: Error + Send + Sync + 'static;
 
+    /// Returns a clock used for session creation and access time
+    ///
+    /// Managers and collectors use this clock unless another one is set,
+    /// so all of them compare the same time.
+    ///
+    /// Default implementation returns [`SystemClock`].
+    fn get_clock(&self) -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+
     /// Returns a list of available session IDs
-    fn get_sessions(&mut self) -> impl Future<Output = Result<Vec<String>, Self::Error>> + Send;
+    fn get_sessions(&self) -> impl Future<Output = Result<Vec<String>, Self::Error>> + Send;
 
     /// Returns the time when session was created in seconds
     ///
@@ -25,25 +43,137 @@ pub trait SessionBackend {
     /// # Arguments
     ///
     /// * session_id - ID of a session
-    fn get_session_age(&mut self, session_id: &str) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send;
+    fn get_session_age(&self, session_id: &str) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send;
+
+    /// Creates an empty session
+    ///
+    /// Returns `false` if session already exists.
+    /// Implementations SHOULD be atomic, so concurrent calls never create the same session twice.
+    ///
+    /// Default implementation only checks that session does not exist with [`SessionBackend::get_session_age`],
+    /// so it is not atomic and the session is stored on the first write.
+    /// Generated IDs are random, so collisions are unlikely,
+    /// but backends shared between processes should override it.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    fn create_session(&self, session_id: &str) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async move { Ok(self.get_session_age(session_id).await?.is_none()) }
+    }
+
+    /// Moves all session data to a new ID
+    ///
+    /// Returns `false` if the session does not exist or a session with the new ID already exists,
+    /// nothing is changed in both cases.
+    /// Creation and access time are preserved.
+    ///
+    /// Default implementation copies values and removes the old session,
+    /// so it is not atomic and does not preserve creation time.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * new_session_id - New ID of the session
+    fn rename_session(
+        &self,
+        session_id: &str,
+        new_session_id: &str,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async move {
+            if self.get_session_age(session_id).await?.is_none() || !self.create_session(new_session_id).await? {
+                return Ok(false);
+            }
+            let values = self.read_all_values(session_id).await?;
+            let values = Vec::from_iter(values.iter().map(|(key, value)| (key.as_str(), value.as_slice())));
+            self.write_values(new_session_id, &values).await?;
+            self.remove_session(session_id).await?;
+            Ok(true)
+        }
+    }
 
     /// Removes a session
     ///
     /// # Arguments
     ///
     /// * session_id - ID of a session
-    fn remove_session(&mut self, session_id: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    fn remove_session(&self, session_id: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Returns the time when session was accessed last time in seconds
+    ///
+    /// Access time SHOULD be updated when values are read or written.
+    /// Returns creation time if session was never accessed and None if session does not exist.
+    ///
+    /// Default implementation does not track access time and returns [`SessionBackend::get_session_age`].
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    fn get_session_access(&self, session_id: &str) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send {
+        self.get_session_age(session_id)
+    }
+
+    /// Returns the time when session was created and accessed last time in seconds
+    ///
+    /// Returns None if session does not exist.
+    ///
+    /// Default implementation calls [`SessionBackend::get_session_age`] and [`SessionBackend::get_session_access`],
+    /// backends SHOULD override it to read both times at once.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    fn get_session_times(
+        &self,
+        session_id: &str,
+    ) -> impl Future<Output = Result<Option<(u64, u64)>, Self::Error>> + Send {
+        async move {
+            let Some(created) = self.get_session_age(session_id).await? else {
+                return Ok(None);
+            };
+            let accessed = self.get_session_access(session_id).await?.unwrap_or(created);
+            Ok(Some((created, accessed)))
+        }
+    }
+
+    /// Sets the time when session was accessed last time to current time
+    ///
+    /// Does nothing if session does not exist.
+    ///
+    /// Default implementation does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    fn touch_session(&self, session_id: &str) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        let _ = session_id;
+        async { Ok(()) }
+    }
 
     /// Read a value from store
     ///
     /// * session_id - ID of a session
     /// * key - Key to read value from
     fn read_value(
-        &mut self,
+        &self,
         session_id: &str,
         key: &str,
     ) -> impl Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send;
 
+    /// Read all values of a session
+    ///
+    /// Returns an empty list when session does not exist.
+    ///
+    /// This method has no default implementation, since keys of a session can not be listed
+    /// using other methods and [`SessionBackend::Error`] can not be constructed here.
+    /// It was added in a breaking release, backends written for earlier versions must implement it.
+    ///
+    /// * session_id - ID of a session
+    fn read_all_values(
+        &self,
+        session_id: &str,
+    ) -> impl Future<Output = Result<Vec<(String, Vec<u8>)>, Self::Error>> + Send;
+
     /// Write a value to store
     ///
     /// # Arguments
@@ -52,7 +182,7 @@ pub trait SessionBackend {
     /// * key - Key to write value to
     /// * value - Value to write
     fn write_value(
-        &mut self,
+        &self,
         session_id: &str,
         key: &str,
         value: &[u8],
@@ -62,5 +192,259 @@ pub trait SessionBackend {
     ///
     /// * session_id - ID of a session
     /// * key - Key to read value from
-    fn remove_value(&mut self, session_id: &str, key: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    fn remove_value(&self, session_id: &str, key: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Read several values from store
+    ///
+    /// Returns values in the same order as keys.
+    ///
+    /// Default implementation calls [`SessionBackend::read_value`] for each key.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * keys - Keys to read values from
+    fn read_values(
+        &self,
+        session_id: &str,
+        keys: &[&str],
+    ) -> impl Future<Output = Result<Vec<Option<Vec<u8>>>, Self::Error>> + Send {
+        async move {
+            let mut result = Vec::with_capacity(keys.len());
+            for key in keys {
+                result.push(self.read_value(session_id, key).await?);
+            }
+            Ok(result)
+        }
+    }
+
+    /// Write several values to store
+    ///
+    /// Default implementation calls [`SessionBackend::write_value`] for each value.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * values - Pairs of key and value to write
+    fn write_values(
+        &self,
+        session_id: &str,
+        values: &[(&str, &[u8])],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for (key, value) in values {
+                self.write_value(session_id, key, value).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Remove several values from store
+    ///
+    /// Default implementation calls [`SessionBackend::remove_value`] for each key.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * keys - Keys to remove values for
+    fn remove_values(&self, session_id: &str, keys: &[&str]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for key in keys {
+                self.remove_value(session_id, key).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Remove values of a session which match a predicate
+    ///
+    /// Predicate receives a key and a value.
+    /// A value is removed only when it was not changed after the predicate was called.
+    /// Used by [`SessionCollector`](crate::SessionCollector) to remove expired values,
+    /// so implementations should not refresh access time of a session.
+    ///
+    /// Default implementation reads values with [`SessionBackend::read_all_values`]
+    /// and removes them with [`SessionBackend::compare_and_swap`],
+    /// so access time is refreshed by backends which track it.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * predicate - Returns `true` for values to remove
+    fn remove_values_if<F>(
+        &self,
+        session_id: &str,
+        predicate: F,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send
+    where
+        F: Fn(&str, &[u8]) -> bool + Send + Sync,
+    {
+        async move {
+            for (key, value) in self.read_all_values(session_id).await? {
+                if predicate(&key, &value) {
+                    self.compare_and_swap(session_id, &key, Some(&value), None).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Replaces a value when the current one equals to expected
+    ///
+    /// Returns `false` when the current value differs and nothing was written.
+    ///
+    /// Default implementation is a plain read followed by a write and is not atomic:
+    /// `Session` serializes its own calls, but other managers and collectors could interleave
+    /// and lose updates. Backends must override it to provide atomicity.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * key - Key to replace value for
+    /// * expected - Expected current value, `None` means that value must not exist
+    /// * value - New value, `None` removes the value
+    fn compare_and_swap(
+        &self,
+        session_id: &str,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async move {
+            let current = self.read_value(session_id, key).await?;
+            if current.as_deref() != expected {
+                return Ok(false);
+            }
+            match value {
+                Some(value) => self.write_value(session_id, key, value).await?,
+                None => self.remove_value(session_id, key).await?,
+            }
+            Ok(true)
+        }
+    }
+
+    /// Increments a counter
+    ///
+    /// Counter is stored as a decimal integer, missing value is treated as zero.
+    /// Returns a new value or `None` when the stored value is not a counter
+    /// or the result does not fit into `i64`.
+    ///
+    /// # Arguments
+    ///
+    /// * session_id - ID of a session
+    /// * key - Key of a counter
+    /// * delta - Value to add
+    fn increment_value(
+        &self,
+        session_id: &str,
+        key: &str,
+        delta: i64,
+    ) -> impl Future<Output = Result<Option<i64>, Self::Error>> + Send {
+        async move {
+            loop {
+                let current = self.read_value(session_id, key).await?;
+                let value = match current.as_deref() {
+                    Some(data) => match decode_counter(data) {
+                        Some(value) => value,
+                        None => return Ok(None),
+                    },
+                    None => 0,
+                };
+                let Some(value) = value.checked_add(delta) else {
+                    return Ok(None);
+                };
+                if self
+                    .compare_and_swap(session_id, key, current.as_deref(), Some(&encode_counter(value)))
+                    .await?
+                {
+                    return Ok(Some(value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, convert::Infallible, sync::Mutex};
+
+    use super::*;
+
+    /// A backend implementing required methods only
+    #[derive(Default)]
+    struct MemoryBackend {
+        sessions: Mutex<HashMap<String, HashMap<String, Vec<u8>>>>,
+    }
+
+    impl SessionBackend for MemoryBackend {
+        type Error = Infallible;
+
+        async fn get_sessions(&self) -> Result<Vec<String>, Self::Error> {
+            Ok(Vec::from_iter(self.sessions.lock().unwrap().keys().cloned()))
+        }
+
+        async fn get_session_age(&self, session_id: &str) -> Result<Option<u64>, Self::Error> {
+            Ok(self.sessions.lock().unwrap().get(session_id).map(|_| 0))
+        }
+
+        async fn remove_session(&self, session_id: &str) -> Result<(), Self::Error> {
+            self.sessions.lock().unwrap().remove(session_id);
+            Ok(())
+        }
+
+        async fn read_value(&self, session_id: &str, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+            let sessions = self.sessions.lock().unwrap();
+            Ok(sessions.get(session_id).and_then(|values| values.get(key)).cloned())
+        }
+
+        async fn read_all_values(&self, session_id: &str) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+            let sessions = self.sessions.lock().unwrap();
+            Ok(Vec::from_iter(
+                sessions
+                    .get(session_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            ))
+        }
+
+        async fn write_value(&self, session_id: &str, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+            let mut sessions = self.sessions.lock().unwrap();
+            let values = sessions.entry(String::from(session_id)).or_default();
+            values.insert(String::from(key), value.to_vec());
+            Ok(())
+        }
+
+        async fn remove_value(&self, session_id: &str, key: &str) -> Result<(), Self::Error> {
+            if let Some(values) = self.sessions.lock().unwrap().get_mut(session_id) {
+                values.remove(key);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn default_create_session() {
+        let backend = MemoryBackend::default();
+        assert!(backend.create_session("session-id").await.unwrap());
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        assert!(!backend.create_session("session-id").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn default_rename_session() {
+        let backend = MemoryBackend::default();
+        assert!(!backend.rename_session("session-id", "new-session-id").await.unwrap());
+        assert!(backend.get_sessions().await.unwrap().is_empty());
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        assert!(backend.rename_session("session-id", "new-session-id").await.unwrap());
+        assert_eq!(
+            backend.get_sessions().await.unwrap(),
+            vec![String::from("new-session-id")]
+        );
+        assert_eq!(
+            backend.read_value("new-session-id", "key").await.unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+    }
 }
@@ -1,15 +1,188 @@
-use std::{error::Error, fmt, num::ParseIntError, string::FromUtf8Error, time::SystemTimeError};
+use std::{
+    error::Error,
+    fmt,
+    num::ParseIntError,
+    string::FromUtf8Error,
+    sync::{Arc, LazyLock},
+    time::SystemTimeError,
+};
 
-use redis::{AsyncCommands, RedisError};
+use redis::{AsyncCommands, RedisError, Script};
 
-use crate::{backend::SessionBackend, utils::now};
+use crate::{
+    backend::SessionBackend,
+    clock::{Clock, SharedClock, SystemClock},
+    utils::now,
+};
+
+// Every script updates access time of an existing session,
+// creation time is only set when a session does not exist yet
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: session ID, timestamp, keys
+static READ_VALUES: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local result = redis.call('HMGET', KEYS[1], unpack(ARGV, 3))
+        if redis.call('HEXISTS', KEYS[2], ARGV[1]) == 1 then
+            redis.call('HSET', KEYS[3], ARGV[1], ARGV[2])
+        end
+        return result
+        ",
+    )
+});
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: session ID, timestamp
+static READ_ALL_VALUES: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local result = redis.call('HGETALL', KEYS[1])
+        if redis.call('HEXISTS', KEYS[2], ARGV[1]) == 1 then
+            redis.call('HSET', KEYS[3], ARGV[1], ARGV[2])
+        end
+        return result
+        ",
+    )
+});
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: session ID, timestamp, pairs of key and value
+static WRITE_VALUES: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.call('HSETNX', KEYS[2], ARGV[1], ARGV[2])
+        redis.call('HSET', KEYS[1], unpack(ARGV, 3))
+        redis.call('HSET', KEYS[3], ARGV[1], ARGV[2])
+        ",
+    )
+});
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: session ID, timestamp, keys
+static REMOVE_VALUES: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.call('HDEL', KEYS[1], unpack(ARGV, 3))
+        if redis.call('HEXISTS', KEYS[2], ARGV[1]) == 1 then
+            redis.call('HSET', KEYS[3], ARGV[1], ARGV[2])
+        end
+        ",
+    )
+});
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: key, whether value expected, expected value, whether value is set, new value, session ID, timestamp
+static COMPARE_AND_SWAP: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local current = redis.call('HGET', KEYS[1], ARGV[1])
+        local matches
+        if ARGV[2] == '1' then
+            matches = current == ARGV[3]
+        else
+            matches = not current
+        end
+        if matches then
+            if ARGV[4] == '1' then
+                redis.call('HSETNX', KEYS[2], ARGV[6], ARGV[7])
+                redis.call('HSET', KEYS[1], ARGV[1], ARGV[5])
+            else
+                redis.call('HDEL', KEYS[1], ARGV[1])
+            end
+        end
+        if redis.call('HEXISTS', KEYS[2], ARGV[6]) == 1 then
+            redis.call('HSET', KEYS[3], ARGV[6], ARGV[7])
+        end
+        if matches then
+            return 1
+        end
+        return 0
+        ",
+    )
+});
+
+// KEYS: session key, sessions key, accessed key
+// ARGV: key, delta, session ID, timestamp
+static INCREMENT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('HEXISTS', KEYS[2], ARGV[3]) == 1 then
+            redis.call('HSET', KEYS[3], ARGV[3], ARGV[4])
+        end
+        local current = redis.call('HGET', KEYS[1], ARGV[1])
+        if current and not string.match(current, '^%-?%d+$') then
+            return false
+        end
+        local result = redis.pcall('HINCRBY', KEYS[1], ARGV[1], ARGV[2])
+        if type(result) == 'table' and result.err then
+            return false
+        end
+        redis.call('HSETNX', KEYS[2], ARGV[3], ARGV[4])
+        redis.call('HSET', KEYS[3], ARGV[3], ARGV[4])
+        return result
+        ",
+    )
+});
+
+// KEYS: session key, new session key, sessions key, accessed key
+// ARGV: session ID, new session ID
+static RENAME: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local created = redis.call('HGET', KEYS[3], ARGV[1])
+        if not created or redis.call('HEXISTS', KEYS[3], ARGV[2]) == 1 or redis.call('EXISTS', KEYS[2]) == 1 then
+            return 0
+        end
+        redis.call('HSET', KEYS[3], ARGV[2], created)
+        redis.call('HDEL', KEYS[3], ARGV[1])
+        local accessed = redis.call('HGET', KEYS[4], ARGV[1])
+        if accessed then
+            redis.call('HSET', KEYS[4], ARGV[2], accessed)
+            redis.call('HDEL', KEYS[4], ARGV[1])
+        end
+        if redis.call('EXISTS', KEYS[1]) == 1 then
+            redis.call('RENAME', KEYS[1], KEYS[2])
+        end
+        return 1
+        ",
+    )
+});
+
+// KEYS: sessions key, accessed key
+// ARGV: session ID, timestamp
+static TOUCH: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('HEXISTS', KEYS[1], ARGV[1]) == 1 then
+            redis.call('HSET', KEYS[2], ARGV[1], ARGV[2])
+        end
+        ",
+    )
+});
+
+// Unlike other scripts, access time is kept, so idle sessions are still collected
+
+// KEYS: session key
+// ARGV: key, expected value
+static REMOVE_VALUE_IF_EQUAL: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+            redis.call('HDEL', KEYS[1], ARGV[1])
+        end
+        ",
+    )
+});
 
 /// Redis powered session backend
 #[derive(Clone)]
 pub struct RedisBackend<C> {
     namespace: String,
     sessions_key: String,
+    accessed_key: String,
     connection: C,
+    clock: SharedClock,
 }
 
 impl<C> RedisBackend<C> {
@@ -19,19 +192,40 @@ impl<C> RedisBackend<C> {
     ///
     /// * namespace - A prefix string for keys
     /// * connection - A redis connection manager
+    ///
+    /// Connection is cloned for every command,
+    /// so it should be cheap to clone, e.g. `MultiplexedConnection` or `ConnectionManager`.
     pub fn new<N>(namespace: N, connection: C) -> Self
     where
         N: Into<String>,
     {
         let namespace = namespace.into();
         let sessions_key = format!("{namespace}:__seance_sessions");
+        let accessed_key = format!("{namespace}:__seance_accessed");
         Self {
             namespace,
             sessions_key,
+            accessed_key,
             connection,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Sets a clock for session creation and access time
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock<T>(mut self, clock: T) -> Self
+    where
+        T: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn get_timestamp(&self) -> Result<u64, RedisBackendError> {
+        now(self.clock.as_ref()).map_err(RedisBackendError::SetSessionTimestamp)
+    }
+
     fn get_session_key(&self, session_id: &str) -> String {
         format!("{}:{}", self.namespace, session_id)
     }
@@ -39,76 +233,257 @@ impl<C> RedisBackend<C> {
 
 impl<C> SessionBackend for RedisBackend<C>
 where
-    C: AsyncCommands,
+    C: AsyncCommands + Clone + Sync,
 {
     type Error = RedisBackendError;
 
-    async fn get_sessions(&mut self) -> Result<Vec<String>, Self::Error> {
+    fn get_clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<String>, Self::Error> {
         self.connection
+            .clone()
             .hkeys(&self.sessions_key)
             .await
             .map_err(RedisBackendError::GetSessions)
     }
 
-    async fn get_session_age(&mut self, session_id: &str) -> Result<Option<u64>, Self::Error> {
+    async fn get_session_age(&self, session_id: &str) -> Result<Option<u64>, Self::Error> {
         self.connection
+            .clone()
             .hget(&self.sessions_key, session_id)
             .await
             .map_err(RedisBackendError::GetSessionAge)
     }
 
-    async fn remove_session(&mut self, session_id: &str) -> Result<(), Self::Error> {
-        let session_key = self.get_session_key(session_id);
+    async fn get_session_access(&self, session_id: &str) -> Result<Option<u64>, Self::Error> {
+        let accessed: Option<u64> = self
+            .connection
+            .clone()
+            .hget(&self.accessed_key, session_id)
+            .await
+            .map_err(RedisBackendError::GetSessionAge)?;
+        match accessed {
+            Some(accessed) => Ok(Some(accessed)),
+            None => self.get_session_age(session_id).await,
+        }
+    }
+
+    async fn get_session_times(&self, session_id: &str) -> Result<Option<(u64, u64)>, Self::Error> {
+        let (created, accessed): (Option<u64>, Option<u64>) = redis::pipe()
+            .hget(&self.sessions_key, session_id)
+            .hget(&self.accessed_key, session_id)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(RedisBackendError::GetSessionAge)?;
+        Ok(created.map(|created| (created, accessed.unwrap_or(created))))
+    }
+
+    async fn touch_session(&self, session_id: &str) -> Result<(), Self::Error> {
+        let timestamp = self.get_timestamp()?;
+        TOUCH
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(timestamp)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(RedisBackendError::TouchSession)
+    }
+
+    async fn create_session(&self, session_id: &str) -> Result<bool, Self::Error> {
+        let timestamp = self.get_timestamp()?;
         self.connection
+            .clone()
+            .hset_nx(&self.sessions_key, session_id, timestamp)
+            .await
+            .map_err(RedisBackendError::CreateSession)
+    }
+
+    async fn rename_session(&self, session_id: &str, new_session_id: &str) -> Result<bool, Self::Error> {
+        if session_id == new_session_id {
+            return Ok(false);
+        }
+        RENAME
+            .key(self.get_session_key(session_id))
+            .key(self.get_session_key(new_session_id))
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(new_session_id)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(RedisBackendError::RenameSession)
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<(), Self::Error> {
+        let session_key = self.get_session_key(session_id);
+        redis::pipe()
+            .atomic()
             .del(session_key)
+            .hdel(&self.sessions_key, session_id)
+            .hdel(&self.accessed_key, session_id)
+            .exec_async(&mut self.connection.clone())
             .await
             .map_err(RedisBackendError::RemoveSession)
     }
 
-    async fn read_value(&mut self, session_id: &str, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+    async fn read_value(&self, session_id: &str, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        let values = self.read_values(session_id, &[key]).await?;
+        Ok(values.into_iter().next().flatten())
+    }
+
+    async fn read_all_values(&self, session_id: &str) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
         let session_key = self.get_session_key(session_id);
-        // Use additional variable because trait bound for FromRedisValue is not satisfied for some reason
-        let result: Option<Vec<u8>> = self
-            .connection
-            .hget(session_key, key)
+        let timestamp = self.get_timestamp()?;
+        READ_ALL_VALUES
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(timestamp)
+            .invoke_async(&mut self.connection.clone())
             .await
-            .map_err(RedisBackendError::ReadValue)?;
-        Ok(result)
+            .map_err(RedisBackendError::ReadValue)
+    }
+
+    async fn write_value(&self, session_id: &str, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.write_values(session_id, &[(key, value)]).await
     }
 
-    async fn write_value(&mut self, session_id: &str, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+    async fn remove_value(&self, session_id: &str, key: &str) -> Result<(), Self::Error> {
+        self.remove_values(session_id, &[key]).await
+    }
+
+    async fn read_values(&self, session_id: &str, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
         let session_key = self.get_session_key(session_id);
-        let len: i64 = self
-            .connection
-            .hlen(&session_key)
+        let timestamp = self.get_timestamp()?;
+        READ_VALUES
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(timestamp)
+            .arg(keys)
+            .invoke_async(&mut self.connection.clone())
             .await
-            .map_err(RedisBackendError::WriteValue)?;
-        if len == 0 {
-            let timestamp = format!("{}", now().map_err(RedisBackendError::SetSessionTimestamp)?);
-            let _: () = self
-                .connection
-                .hset(&self.sessions_key, session_id, timestamp)
-                .await
-                .map_err(RedisBackendError::WriteValue)?;
+            .map_err(RedisBackendError::ReadValue)
+    }
+
+    async fn write_values(&self, session_id: &str, values: &[(&str, &[u8])]) -> Result<(), Self::Error> {
+        if values.is_empty() {
+            return Ok(());
         }
-        self.connection
-            .hset(session_key, key, value)
+        let session_key = self.get_session_key(session_id);
+        let timestamp = self.get_timestamp()?;
+        WRITE_VALUES
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(timestamp)
+            .arg(values)
+            .invoke_async(&mut self.connection.clone())
             .await
             .map_err(RedisBackendError::WriteValue)
     }
 
-    async fn remove_value(&mut self, session_id: &str, key: &str) -> Result<(), Self::Error> {
+    async fn remove_values(&self, session_id: &str, keys: &[&str]) -> Result<(), Self::Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
         let session_key = self.get_session_key(session_id);
-        self.connection
-            .hdel(session_key, key)
+        let timestamp = self.get_timestamp()?;
+        REMOVE_VALUES
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(session_id)
+            .arg(timestamp)
+            .arg(keys)
+            .invoke_async(&mut self.connection.clone())
             .await
             .map_err(RedisBackendError::RemoveValue)
     }
+
+    async fn remove_values_if<F>(&self, session_id: &str, predicate: F) -> Result<(), Self::Error>
+    where
+        F: Fn(&str, &[u8]) -> bool + Send + Sync,
+    {
+        let session_key = self.get_session_key(session_id);
+        let values: Vec<(String, Vec<u8>)> = self
+            .connection
+            .clone()
+            .hgetall(&session_key)
+            .await
+            .map_err(RedisBackendError::ReadValue)?;
+        for (key, value) in values {
+            if predicate(&key, &value) {
+                REMOVE_VALUE_IF_EQUAL
+                    .key(&session_key)
+                    .arg(key)
+                    .arg(value)
+                    .invoke_async::<()>(&mut self.connection.clone())
+                    .await
+                    .map_err(RedisBackendError::RemoveValue)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn compare_and_swap(
+        &self,
+        session_id: &str,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool, Self::Error> {
+        let session_key = self.get_session_key(session_id);
+        let timestamp = self.get_timestamp()?;
+        COMPARE_AND_SWAP
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(key)
+            .arg(expected.is_some())
+            .arg(expected.unwrap_or_default())
+            .arg(value.is_some())
+            .arg(value.unwrap_or_default())
+            .arg(session_id)
+            .arg(timestamp)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(RedisBackendError::CompareAndSwap)
+    }
+
+    async fn increment_value(&self, session_id: &str, key: &str, delta: i64) -> Result<Option<i64>, Self::Error> {
+        let session_key = self.get_session_key(session_id);
+        let timestamp = self.get_timestamp()?;
+        INCREMENT
+            .key(session_key)
+            .key(&self.sessions_key)
+            .key(&self.accessed_key)
+            .arg(key)
+            .arg(delta)
+            .arg(session_id)
+            .arg(timestamp)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(RedisBackendError::IncrementValue)
+    }
 }
 
 /// An error occurred in redis backend
 #[derive(Debug)]
 pub enum RedisBackendError {
+    /// Failed to compare and swap value
+    CompareAndSwap(RedisError),
+    /// Failed to create session
+    CreateSession(RedisError),
     /// Failed to get sessions list
     GetSessions(RedisError),
     /// Failed to get session age
@@ -117,18 +492,24 @@ pub enum RedisBackendError {
     ParseSessionAge(ParseIntError),
     /// Failed to parse session ID
     ParseSessionId(FromUtf8Error),
+    /// Failed to increment value
+    IncrementValue(RedisError),
     /// Failed to read value
     ReadValue(RedisError),
     /// Failed to remove session
     RemoveSession(RedisError),
     /// Failed to remove value
     RemoveValue(RedisError),
+    /// Failed to rename session
+    RenameSession(RedisError),
     /// Failed to read session age
     SessionAgeFromUtf8(FromUtf8Error),
     /// Failed to set session timestamp
     ///
     /// An error occurred when getting system time
     SetSessionTimestamp(SystemTimeError),
+    /// Failed to update session access time
+    TouchSession(RedisError),
     /// Failed to write value
     WriteValue(RedisError),
 }
@@ -137,15 +518,20 @@ impl fmt::Display for RedisBackendError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::RedisBackendError::*;
         match self {
+            CompareAndSwap(err) => write!(out, "failed to compare and swap value: {err}"),
+            CreateSession(err) => write!(out, "failed to create session: {err}"),
             GetSessions(err) => write!(out, "failed to get sessions list: {err}"),
             GetSessionAge(err) => write!(out, "failed to get session age: {err}"),
             ParseSessionAge(err) => write!(out, "session age contains non-integer value: {err}"),
             ParseSessionId(err) => write!(out, "session id contains non-utf8 string: {err}"),
+            IncrementValue(err) => write!(out, "failed to increment value: {err}"),
             ReadValue(err) => write!(out, "failed to read value: {err}"),
             RemoveSession(err) => write!(out, "failed to remove session: {err}"),
             RemoveValue(err) => write!(out, "failed to remove value: {err}"),
+            RenameSession(err) => write!(out, "failed to rename session: {err}"),
             SessionAgeFromUtf8(err) => write!(out, "session age contains non-utf8 string: {err}"),
             SetSessionTimestamp(err) => write!(out, "failed to set session timestamp: {err}"),
+            TouchSession(err) => write!(out, "failed to update session access time: {err}"),
             WriteValue(err) => write!(out, "failed to write value: {err}"),
         }
     }
@@ -155,15 +541,20 @@ impl Error for RedisBackendError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::RedisBackendError::*;
         Some(match self {
+            CompareAndSwap(err) => err,
+            CreateSession(err) => err,
             GetSessions(err) => err,
             GetSessionAge(err) => err,
             ParseSessionAge(err) => err,
             ParseSessionId(err) => err,
+            IncrementValue(err) => err,
             ReadValue(err) => err,
             RemoveSession(err) => err,
             RemoveValue(err) => err,
+            RenameSession(err) => err,
             SessionAgeFromUtf8(err) => err,
             SetSessionTimestamp(err) => err,
+            TouchSession(err) => err,
             WriteValue(err) => err,
         })
     }
@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::runtime::Handle;
+
+use crate::{
+    backend::SessionBackend,
+    codec::{Codec, JsonCodec},
+    key::SessionKey,
+    session::{Session, SessionError, parse_value},
+    value::{Value, ValueRef},
+};
+
+/// A session which keeps values in memory
+///
+/// Values are loaded on first access with a single backend call,
+/// changes are written back by [`BufferedSession::commit`].
+/// Keys changed by the session overwrite values written by other processes in the meantime.
+///
+/// Created by [`Session::into_buffered`].
+pub struct BufferedSession<B, C = JsonCodec>
+where
+    B: SessionBackend + 'static,
+    C: Codec + 'static,
+{
+    session: Session<B, C>,
+    values: Option<HashMap<String, Vec<u8>>>,
+    changed: HashSet<String>,
+    commit_on_drop: bool,
+}
+
+impl<B, C> BufferedSession<B, C>
+where
+    B: SessionBackend + 'static,
+    C: Codec + 'static,
+{
+    pub(crate) fn new(session: Session<B, C>) -> Self {
+        Self {
+            session,
+            values: None,
+            changed: HashSet::new(),
+            commit_on_drop: false,
+        }
+    }
+
+    /// Whether to commit changes when session is dropped
+    ///
+    /// Changes are written by a task spawned on the current tokio runtime,
+    /// errors are logged. Default is `false`.
+    pub fn commit_on_drop(mut self, commit_on_drop: bool) -> Self {
+        self.commit_on_drop = commit_on_drop;
+        self
+    }
+
+    /// Whether session contains changes which are not committed
+    pub fn is_dirty(&self) -> bool {
+        !self.changed.is_empty()
+    }
+
+    async fn load(&mut self) -> Result<&mut HashMap<String, Vec<u8>>, SessionError> {
+        if self.values.is_none() {
+            let _lock = self.session.lock.lock().await;
+            self.session.check_lifetime().await?;
+            let values = self
+                .session
+                .context
+                .backend
+                .read_all_values(&self.session.id)
+                .await
+                .map_err(SessionError::backend)?;
+            self.values = Some(HashMap::from_iter(values));
+        }
+        Ok(self.values.get_or_insert_default())
+    }
+
+    async fn read_value(&mut self, key: &str) -> Result<Option<Value>, SessionError> {
+        match self.load().await?.get(key) {
+            Some(value) => Ok(Some(Value::decode(value).map_err(SessionError::DecodeValue)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn write_value(&mut self, key: &str, value: Vec<u8>) -> Result<(), SessionError> {
+        self.load().await?.insert(String::from(key), value);
+        self.changed.insert(String::from(key));
+        Ok(())
+    }
+
+    /// Sets a value for key
+    ///
+    /// Expiration time of an existing value is preserved.
+    pub async fn set<K, V>(&mut self, key: K, value: &V) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+        V: Serialize,
+    {
+        let key = key.as_ref();
+        let mut new_value = ValueRef::new(&value);
+        if let Some(old_value) = self.read_value(key).await?
+            && !self.session.context.is_expired(&old_value)?
+            && let Some(expires_at) = old_value.get_expires_at()
+        {
+            new_value.set_expires_at(expires_at);
+        }
+        let new_value = self.session.context.encode_value(key, new_value)?;
+        self.write_value(key, new_value).await
+    }
+
+    /// Sets a value for key which expires after given lifetime
+    pub async fn set_with_ttl<K, V>(&mut self, key: K, value: &V, lifetime: Duration) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+        V: Serialize,
+    {
+        let key = key.as_ref();
+        let mut new_value = ValueRef::new(&value);
+        new_value
+            .set_lifetime(lifetime, self.session.context.clock.as_ref())
+            .map_err(SessionError::ExpireValue)?;
+        let new_value = self.session.context.encode_value(key, new_value)?;
+        self.write_value(key, new_value).await
+    }
+
+    /// Gets a value for key
+    pub async fn get<K, O>(&mut self, key: K) -> Result<Option<O>, SessionError>
+    where
+        K: AsRef<str>,
+        O: DeserializeOwned,
+    {
+        let key = key.as_ref();
+        match self.read_value(key).await? {
+            Some(value) => parse_value(&self.session.context, key, value),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a value for a typed key
+    pub async fn get_typed<T>(&mut self, key: &SessionKey<T>) -> Result<Option<T>, SessionError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get(key).await
+    }
+
+    /// Sets a value for a typed key
+    ///
+    /// Value expires after the key lifetime if it is set,
+    /// otherwise expiration time of an existing value is preserved.
+    pub async fn set_typed<T>(&mut self, key: &SessionKey<T>, value: &T) -> Result<(), SessionError>
+    where
+        T: Serialize,
+    {
+        match key.get_ttl() {
+            Some(ttl) => self.set_with_ttl(key, value, ttl).await,
+            None => self.set(key, value).await,
+        }
+    }
+
+    /// Expires a key after given lifetime
+    pub async fn expire<K>(&mut self, key: K, lifetime: Duration) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        if let Some(mut value) = self.read_value(key).await? {
+            value
+                .set_lifetime(lifetime, self.session.context.clock.as_ref())
+                .map_err(SessionError::ExpireValue)?;
+            let value = value.encode().map_err(SessionError::EncodeValue)?;
+            self.write_value(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Expires a key at given time
+    pub async fn expire_at<K>(&mut self, key: K, expires_at: SystemTime) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        if let Some(mut value) = self.read_value(key).await? {
+            value.set_expires_at(expires_at).map_err(SessionError::ExpireValue)?;
+            let value = value.encode().map_err(SessionError::EncodeValue)?;
+            self.write_value(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes expiration time of a key
+    ///
+    /// Expired values are not restored.
+    pub async fn persist<K>(&mut self, key: K) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        if let Some(mut value) = self.read_value(key).await?
+            && value.get_expires_at().is_some()
+            && !self.session.context.is_expired(&value)?
+        {
+            value.persist();
+            let value = value.encode().map_err(SessionError::EncodeValue)?;
+            self.write_value(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes a key
+    pub async fn remove<K>(&mut self, key: K) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        self.load().await?.remove(key);
+        self.changed.insert(String::from(key));
+        Ok(())
+    }
+
+    /// Writes changed values to backend
+    ///
+    /// Changes are kept on error, so commit can be retried.
+    pub async fn commit(&mut self) -> Result<(), SessionError> {
+        let changes = self.take_changes();
+        if let Err(err) = write_changes(&self.session, &changes).await {
+            self.changed.extend(changes.into_iter().map(|(key, _)| key));
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Drops changes which are not committed
+    ///
+    /// Values are loaded again on next access.
+    pub fn rollback(&mut self) {
+        self.values = None;
+        self.changed.clear();
+    }
+
+    fn take_changes(&mut self) -> Vec<(String, Option<Vec<u8>>)> {
+        let values = self.values.as_ref();
+        Vec::from_iter(self.changed.drain().map(|key| {
+            let value = values.and_then(|values| values.get(&key)).cloned();
+            (key, value)
+        }))
+    }
+}
+
+impl<B, C> Drop for BufferedSession<B, C>
+where
+    B: SessionBackend + 'static,
+    C: Codec + 'static,
+{
+    fn drop(&mut self) {
+        if !self.commit_on_drop || !self.is_dirty() {
+            return;
+        }
+        let changes = self.take_changes();
+        let session = self.session.clone();
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(err) = write_changes(&session, &changes).await {
+                        log::error!("Failed to commit session '{}': {err}", session.id);
+                    }
+                });
+            }
+            Err(err) => log::error!("Failed to commit session '{}': {err}", session.id),
+        }
+    }
+}
+
+/// Writes changes to backend with one call for written and one for removed values
+async fn write_changes<B, C>(session: &Session<B, C>, changes: &[(String, Option<Vec<u8>>)]) -> Result<(), SessionError>
+where
+    B: SessionBackend,
+    C: Codec,
+{
+    let mut written = Vec::new();
+    let mut removed = Vec::new();
+    for (key, value) in changes {
+        match value {
+            Some(value) => written.push((key.as_str(), value.as_slice())),
+            None => removed.push(key.as_str()),
+        }
+    }
+    let _lock = session.lock.lock().await;
+    let backend = &session.context.backend;
+    if !written.is_empty() {
+        backend
+            .write_values(&session.id, &written)
+            .await
+            .map_err(SessionError::backend)?;
+    }
+    if !removed.is_empty() {
+        backend
+            .remove_values(&session.id, &removed)
+            .await
+            .map_err(SessionError::backend)?;
+    }
+    Ok(())
+}
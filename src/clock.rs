@@ -0,0 +1,92 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+/// A source of current time
+///
+/// Used for session ages, access time and value expiration,
+/// so tests are able to control time instead of sleeping.
+pub trait Clock: Send + Sync {
+    /// Returns current time
+    fn now(&self) -> SystemTime;
+}
+
+/// A clock shared between a manager, backends and collectors
+pub(crate) type SharedClock = Arc<dyn Clock>;
+
+/// A clock which returns system time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock which time is changed manually
+///
+/// Clones share the same time, so a single clock could be passed to a manager, a backend and a collector.
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Arc<Mutex<SystemTime>>,
+}
+
+impl ManualClock {
+    /// Creates a new clock
+    ///
+    /// # Arguments
+    ///
+    /// * time - Initial time
+    pub fn new(time: SystemTime) -> Self {
+        Self {
+            time: Arc::new(Mutex::new(time)),
+        }
+    }
+
+    /// Sets current time
+    pub fn set(&self, time: SystemTime) {
+        *self.time.lock().unwrap_or_else(PoisonError::into_inner) = time;
+    }
+
+    /// Moves current time forward
+    pub fn advance(&self, duration: Duration) {
+        *self.time.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+}
+
+impl Default for ManualClock {
+    /// Creates a clock starting at current system time
+    fn default() -> Self {
+        Self::new(SystemTime::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.time.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        out.debug_struct("ManualClock").field("time", &self.now()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH);
+        let other = clock.clone();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(other.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(5));
+        other.set(SystemTime::UNIX_EPOCH);
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,320 @@
+use std::{error::Error, fmt};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Error as JsonError;
+
+/// A value codec interface
+///
+/// Codec encodes values stored in sessions.
+/// Every stored value starts with a format tag,
+/// so values written with another codec are still decoded when its feature is enabled.
+pub trait Codec: Send + Sync {
+    /// Format tag of encoded values
+    ///
+    /// Tag MUST be unique, tags `0x01`-`0x0F` are reserved for built-in codecs.
+    /// Tags `{`, `-` and ASCII digits are used by JSON values and counters.
+    const TAG: u8;
+
+    /// An error occurred when encoding or decoding a value
+    type Error: Error + Send + Sync + 'static;
+
+    /// Encodes a value
+    ///
+    /// # Arguments
+    ///
+    /// * value - Value to encode
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: Serialize + ?Sized;
+
+    /// Decodes a value
+    ///
+    /// # Arguments
+    ///
+    /// * data - Encoded value
+    fn decode<T>(&self, data: &[u8]) -> Result<T, Self::Error>
+    where
+        T: DeserializeOwned;
+}
+
+/// JSON codec
+///
+/// Values are stored as JSON objects with an expiration time,
+/// the same way as previous versions of the crate did.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    const TAG: u8 = b'{';
+
+    type Error = JsonError;
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        serde_json::to_vec(value)
+    }
+
+    fn decode<T>(&self, data: &[u8]) -> Result<T, Self::Error>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(data)
+    }
+}
+
+/// MessagePack codec
+///
+/// Structs are encoded as maps, so fields could be added and reordered.
+#[cfg_attr(nightly, doc(cfg(feature = "msgpack")))]
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+    const TAG: u8 = 0x01;
+
+    type Error = MessagePackError;
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        rmp_serde::to_vec_named(value).map_err(MessagePackError::Encode)
+    }
+
+    fn decode<T>(&self, data: &[u8]) -> Result<T, Self::Error>
+    where
+        T: DeserializeOwned,
+    {
+        rmp_serde::from_slice(data).map_err(MessagePackError::Decode)
+    }
+}
+
+/// An error occurred in MessagePack codec
+#[cfg_attr(nightly, doc(cfg(feature = "msgpack")))]
+#[cfg(feature = "msgpack")]
+#[derive(Debug)]
+pub enum MessagePackError {
+    /// Failed to decode value
+    Decode(rmp_serde::decode::Error),
+    /// Failed to encode value
+    Encode(rmp_serde::encode::Error),
+}
+
+#[cfg(feature = "msgpack")]
+impl Error for MessagePackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MessagePackError::Decode(err) => Some(err),
+            MessagePackError::Encode(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl fmt::Display for MessagePackError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessagePackError::Decode(err) => write!(out, "failed to decode MessagePack: {err}"),
+            MessagePackError::Encode(err) => write!(out, "failed to encode MessagePack: {err}"),
+        }
+    }
+}
+
+/// CBOR codec
+#[cfg_attr(nightly, doc(cfg(feature = "cbor")))]
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    const TAG: u8 = 0x02;
+
+    type Error = CborError;
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut data = Vec::new();
+        ciborium::into_writer(value, &mut data).map_err(CborError::Encode)?;
+        Ok(data)
+    }
+
+    fn decode<T>(&self, data: &[u8]) -> Result<T, Self::Error>
+    where
+        T: DeserializeOwned,
+    {
+        ciborium::from_reader(data).map_err(CborError::Decode)
+    }
+}
+
+/// An error occurred in CBOR codec
+#[cfg_attr(nightly, doc(cfg(feature = "cbor")))]
+#[cfg(feature = "cbor")]
+#[derive(Debug)]
+pub enum CborError {
+    /// Failed to decode value
+    Decode(ciborium::de::Error<std::io::Error>),
+    /// Failed to encode value
+    Encode(ciborium::ser::Error<std::io::Error>),
+}
+
+#[cfg(feature = "cbor")]
+impl Error for CborError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CborError::Decode(err) => Some(err),
+            CborError::Encode(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl fmt::Display for CborError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CborError::Decode(err) => write!(out, "failed to decode CBOR: {err}"),
+            CborError::Encode(err) => write!(out, "failed to encode CBOR: {err}"),
+        }
+    }
+}
+
+/// Bincode codec
+///
+/// Bincode is not self-describing, so values must be decoded into the same types they were encoded from.
+#[cfg_attr(nightly, doc(cfg(feature = "bincode")))]
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    const TAG: u8 = 0x03;
+
+    type Error = BincodeError;
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(BincodeError::Encode)
+    }
+
+    fn decode<T>(&self, data: &[u8]) -> Result<T, Self::Error>
+    where
+        T: DeserializeOwned,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::standard())
+            .map(|(value, _)| value)
+            .map_err(BincodeError::Decode)
+    }
+}
+
+/// An error occurred in bincode codec
+#[cfg_attr(nightly, doc(cfg(feature = "bincode")))]
+#[cfg(feature = "bincode")]
+#[derive(Debug)]
+pub enum BincodeError {
+    /// Failed to decode value
+    Decode(bincode::error::DecodeError),
+    /// Failed to encode value
+    Encode(bincode::error::EncodeError),
+}
+
+#[cfg(feature = "bincode")]
+impl Error for BincodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BincodeError::Decode(err) => Some(err),
+            BincodeError::Encode(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl fmt::Display for BincodeError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BincodeError::Decode(err) => write!(out, "failed to decode bincode: {err}"),
+            BincodeError::Encode(err) => write!(out, "failed to encode bincode: {err}"),
+        }
+    }
+}
+
+/// Whether there is a built-in codec for the format tag
+#[allow(clippy::match_like_matches_macro)]
+pub(crate) fn is_builtin(tag: u8) -> bool {
+    match tag {
+        #[cfg(feature = "msgpack")]
+        MessagePackCodec::TAG => true,
+        #[cfg(feature = "cbor")]
+        CborCodec::TAG => true,
+        #[cfg(feature = "bincode")]
+        BincodeCodec::TAG => true,
+        _ => false,
+    }
+}
+
+/// Decodes a value using a built-in codec for the format tag
+///
+/// Returns `None` when there is no codec for the tag.
+#[cfg_attr(
+    not(any(feature = "msgpack", feature = "cbor", feature = "bincode")),
+    allow(unused_variables)
+)]
+pub(crate) fn decode_builtin<T>(tag: u8, data: &[u8]) -> Option<Result<T, CodecError>>
+where
+    T: DeserializeOwned,
+{
+    match tag {
+        #[cfg(feature = "msgpack")]
+        MessagePackCodec::TAG => Some(MessagePackCodec.decode(data).map_err(CodecError::codec)),
+        #[cfg(feature = "cbor")]
+        CborCodec::TAG => Some(CborCodec.decode(data).map_err(CodecError::codec)),
+        #[cfg(feature = "bincode")]
+        BincodeCodec::TAG => Some(BincodeCodec.decode(data).map_err(CodecError::codec)),
+        _ => None,
+    }
+}
+
+/// An error occurred when encoding or decoding a stored value
+#[derive(Debug)]
+pub enum CodecError {
+    /// Codec error
+    Codec(Box<dyn Error + Send + Sync>),
+    /// Stored value is shorter than its header
+    Truncated,
+    /// There is no codec for the format tag of a stored value
+    UnknownFormat(u8),
+}
+
+impl CodecError {
+    pub(crate) fn codec<E: Error + Send + Sync + 'static>(err: E) -> Self {
+        Self::Codec(Box::new(err))
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CodecError::Codec(err) => Some(err.as_ref()),
+            CodecError::Truncated => None,
+            CodecError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Codec(err) => write!(out, "{err}"),
+            CodecError::Truncated => write!(out, "value is truncated"),
+            CodecError::UnknownFormat(tag) => write!(out, "unknown value format: {tag:#04x}"),
+        }
+    }
+}
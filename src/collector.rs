@@ -1,17 +1,37 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use tokio::{
     sync::mpsc::{Receiver, Sender, channel},
     time::interval,
 };
 
-use crate::{backend::SessionBackend, utils::now};
+use crate::{
+    backend::SessionBackend,
+    clock::{Clock, SharedClock},
+    utils::{duration_to_secs, now},
+    value::Value,
+};
+
+/// Describes which time is compared with session lifetime
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CollectorMode {
+    /// Remove sessions created earlier than lifetime ago
+    #[default]
+    Created,
+    /// Remove sessions which were not accessed during lifetime
+    ///
+    /// See [`SessionBackend::get_session_access`].
+    Idle,
+}
 
 /// Garbage collector for sessions
 pub struct SessionCollector<B> {
     backend: B,
     period: Duration,
     lifetime: Duration,
+    mode: CollectorMode,
+    clock: SharedClock,
+    remove_expired_values: bool,
     sender: Sender<()>,
     receiver: Receiver<()>,
 }
@@ -28,18 +48,51 @@ where
     ///
     /// * backend - Store backend
     /// * period - Interval between GC calls
-    /// * lifetime - Minimum session lifetime
+    /// * lifetime - Minimum session lifetime, rounded up to a whole second
     pub fn new(backend: B, period: Duration, lifetime: Duration) -> Self {
         let (sender, receiver) = channel(1);
         Self {
+            clock: backend.get_clock(),
             backend,
             period,
             lifetime,
+            mode: CollectorMode::default(),
+            remove_expired_values: false,
             sender,
             receiver,
         }
     }
 
+    /// Sets which time is compared with session lifetime
+    ///
+    /// Default is [`CollectorMode::Created`].
+    pub fn mode(mut self, mode: CollectorMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets a clock to compute session age
+    ///
+    /// Default is the clock of a backend, see [`SessionBackend::get_clock`].
+    /// Period between GC calls always uses tokio timer.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Enables removing expired values of sessions which are not collected
+    ///
+    /// Every value of every session is read on each run, so it is disabled by default.
+    /// Access time of sessions is not refreshed by backends which support it,
+    /// see [`SessionBackend::remove_values_if`].
+    pub fn remove_expired_values(mut self, enabled: bool) -> Self {
+        self.remove_expired_values = enabled;
+        self
+    }
+
     /// Returns a session collector handle
     pub fn get_handle(&self) -> SessionCollectorHandle {
         SessionCollectorHandle {
@@ -47,27 +100,53 @@ where
         }
     }
 
+    /// Runs GC once
+    ///
+    /// Use this method to collect sessions on your own schedule, e.g. in tests.
+    pub async fn collect_once(&mut self) {
+        if let Err(err) = self.collect().await {
+            log::error!("An error occurred in session GC: {err}")
+        }
+    }
+
     async fn collect(&mut self) -> Result<(), String> {
-        let lifetime = self.lifetime.as_secs();
+        let lifetime = duration_to_secs(self.lifetime);
         let session_ids = self.backend.get_sessions().await.map_err(|err| err.to_string())?;
-        let timestamp = now().map_err(|err| err.to_string())?;
+        let timestamp = now(self.clock.as_ref()).map_err(|err| err.to_string())?;
         for session_id in session_ids {
-            if let Some(age) = self
-                .backend
-                .get_session_age(&session_id)
-                .await
-                .map_err(|err| err.to_string())?
-                && timestamp - age >= lifetime
-            {
-                self.backend
-                    .remove_session(&session_id)
-                    .await
-                    .map_err(|err| err.to_string())?;
+            // A single broken session must not prevent collecting others
+            if let Err(err) = self.collect_session(&session_id, timestamp, lifetime).await {
+                log::error!("Failed to collect session '{session_id}': {err}")
             }
         }
         Ok(())
     }
 
+    async fn collect_session(&mut self, session_id: &str, timestamp: u64, lifetime: u64) -> Result<(), B::Error> {
+        let time = match self.mode {
+            CollectorMode::Created => self.backend.get_session_age(session_id).await?,
+            CollectorMode::Idle => self.backend.get_session_access(session_id).await?,
+        };
+        if let Some(time) = time
+            && timestamp.saturating_sub(time) >= lifetime
+        {
+            return self.backend.remove_session(session_id).await;
+        }
+        if self.remove_expired_values {
+            let clock = self.clock.as_ref();
+            self.backend
+                .remove_values_if(session_id, |_, data| {
+                    // Values which could not be decoded are left for a session to report
+                    Value::decode(data)
+                        .ok()
+                        .and_then(|value| value.is_expired(clock).ok())
+                        .unwrap_or(false)
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
     /// Starts GC loop
     pub async fn run(&mut self) {
         let mut interval = interval(self.period);
@@ -77,9 +156,7 @@ where
                 break;
             }
             interval.tick().await;
-            if let Err(err) = self.collect().await {
-                log::error!("An error occurred in session GC: {err}")
-            }
+            self.collect_once().await;
         }
     }
 }
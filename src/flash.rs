@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+/// Session key which stores flash messages
+///
+/// Keys starting with `__seance_` are reserved, see [`Session::set`](crate::Session::set).
+pub(crate) const FLASH_KEY: &str = "__seance_flash";
+
+/// A one-shot message kept in a session until it is read
+///
+/// See [`Session::flash`](crate::Session::flash).
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Flash {
+    /// Kind of the message, e.g. `error` or `success`
+    pub kind: String,
+    /// Text of the message
+    pub message: String,
+}
+
+impl Flash {
+    /// Creates a new message
+    ///
+    /// # Arguments
+    ///
+    /// * kind - Kind of the message
+    /// * message - Text of the message
+    pub fn new<K, M>(kind: K, message: M) -> Self
+    where
+        K: Into<String>,
+        M: Into<String>,
+    {
+        Self {
+            kind: kind.into(),
+            message: message.into(),
+        }
+    }
+}
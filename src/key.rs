@@ -0,0 +1,66 @@
+use std::{fmt, marker::PhantomData, time::Duration};
+
+/// A session key with a value type
+///
+/// Declare keys once, e.g. as constants, and use them with [`Session::get_typed`](crate::Session::get_typed)
+/// and [`Session::set_typed`](crate::Session::set_typed),
+/// so a key is never written with different types.
+pub struct SessionKey<T> {
+    name: &'static str,
+    ttl: Option<Duration>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> SessionKey<T> {
+    /// Creates a new key
+    ///
+    /// # Arguments
+    ///
+    /// * name - Name of the key
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ttl: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets lifetime of values written with the key
+    pub const fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns name of the key
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns lifetime of values written with the key
+    pub const fn get_ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+}
+
+impl<T> AsRef<str> for SessionKey<T> {
+    fn as_ref(&self) -> &str {
+        self.name
+    }
+}
+
+impl<T> Clone for SessionKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SessionKey<T> {}
+
+impl<T> fmt::Debug for SessionKey<T> {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        out.debug_struct("SessionKey")
+            .field("name", &self.name)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
@@ -3,16 +3,35 @@
 #![warn(missing_docs)]
 
 pub use self::{
-    collector::{SessionCollector, SessionCollectorHandle},
-    manager::SessionManager,
+    buffered::BufferedSession,
+    clock::{Clock, ManualClock, SystemClock},
+    collector::{CollectorMode, SessionCollector, SessionCollectorHandle},
+    flash::Flash,
+    key::SessionKey,
+    manager::{SessionManager, SessionManagerBuilder},
+    namespace::SessionNamespace,
     session::{Session, SessionError},
 };
 
+#[cfg_attr(nightly, doc(cfg(feature = "derive")))]
+#[cfg(feature = "derive")]
+pub use seance_derive::SessionData;
+
+mod buffered;
+mod clock;
 mod collector;
+mod flash;
+mod key;
+mod lock;
 mod manager;
+mod migration;
+mod namespace;
 mod session;
 mod utils;
 mod value;
 
 /// Store backend implementations
 pub mod backend;
+
+/// Value codecs
+pub mod codec;
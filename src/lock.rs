@@ -0,0 +1,73 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex, Weak},
+};
+
+use tokio::sync::Mutex;
+
+const MIN_CLEANUP_THRESHOLD: usize = 64;
+
+/// A lock for operations on a single session
+pub(crate) type SessionLock = Arc<Mutex<()>>;
+
+/// Registry of locks for sessions used in current process
+///
+/// A lock lives as long as there is a session holding it.
+pub(crate) struct SessionLocks {
+    inner: StdMutex<SessionLocksInner>,
+}
+
+struct SessionLocksInner {
+    locks: HashMap<String, Weak<Mutex<()>>>,
+    cleanup_threshold: usize,
+}
+
+impl SessionLocks {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: StdMutex::new(SessionLocksInner {
+                locks: HashMap::new(),
+                cleanup_threshold: MIN_CLEANUP_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Returns a lock for a session ID
+    pub(crate) fn get(&self, session_id: &str) -> SessionLock {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(lock) = inner.locks.get(session_id).and_then(Weak::upgrade) {
+            return lock;
+        }
+        if inner.locks.len() >= inner.cleanup_threshold {
+            inner.locks.retain(|_, lock| lock.strong_count() > 0);
+            inner.cleanup_threshold = usize::max(inner.locks.len() * 2, MIN_CLEANUP_THRESHOLD);
+        }
+        let lock = Arc::new(Mutex::new(()));
+        inner.locks.insert(String::from(session_id), Arc::downgrade(&lock));
+        lock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_locks() {
+        let locks = SessionLocks::new();
+        let lock = locks.get("session-id");
+        assert!(Arc::ptr_eq(&lock, &locks.get("session-id")));
+        assert!(!Arc::ptr_eq(&lock, &locks.get("other-session-id")));
+
+        let held = Vec::from_iter((0..MIN_CLEANUP_THRESHOLD).map(|idx| locks.get(&format!("held-{idx}"))));
+        for idx in 0..MIN_CLEANUP_THRESHOLD * 4 {
+            locks.get(&format!("dropped-{idx}"));
+        }
+        let inner = locks.inner.lock().unwrap();
+        assert!(inner.locks.len() <= inner.cleanup_threshold);
+        assert!(inner.locks.contains_key("session-id"));
+        for idx in 0..held.len() {
+            assert!(inner.locks.contains_key(&format!("held-{idx}")));
+        }
+    }
+}
@@ -1,11 +1,37 @@
-use crate::{backend::SessionBackend, session::Session};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use crate::{
+    backend::SessionBackend,
+    clock::{Clock, SharedClock},
+    codec::{Codec, JsonCodec},
+    lock::SessionLocks,
+    migration::Migrations,
+    session::{Session, SessionError},
+    utils::{duration_to_secs, generate_id, is_valid_id},
+    value::{Value, ValueRef},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{sync::Arc, time::Duration};
+
+const DEFAULT_ID_LENGTH: usize = 32;
+const MIN_ID_LENGTH: usize = 16;
 
 /// A session manager
-#[derive(Clone)]
-pub struct SessionManager<B> {
-    backend: Arc<Mutex<B>>,
+pub struct SessionManager<B, C = JsonCodec> {
+    context: Arc<SessionContext<B, C>>,
+}
+
+/// State shared between a manager and its sessions
+pub(crate) struct SessionContext<B, C> {
+    pub(crate) backend: B,
+    pub(crate) codec: C,
+    pub(crate) migrations: Migrations,
+    pub(crate) clock: SharedClock,
+    pub(crate) locks: SessionLocks,
+    /// Maximum session age in seconds
+    pub(crate) max_age: Option<u64>,
+    /// Maximum time between session accesses in seconds
+    pub(crate) idle_timeout: Option<u64>,
+    /// Length of generated session IDs
+    pub(crate) id_length: usize,
 }
 
 impl<B> SessionManager<B>
@@ -18,16 +44,239 @@ where
     ///
     /// * backend - A session backend
     pub fn new(backend: B) -> Self {
-        Self {
-            backend: Arc::new(Mutex::new(backend)),
+        Self::builder(backend).build()
+    }
+
+    /// Returns a builder to configure a new session manager
+    ///
+    /// # Arguments
+    ///
+    /// * backend - A session backend
+    pub fn builder(backend: B) -> SessionManagerBuilder<B> {
+        SessionManagerBuilder {
+            clock: backend.get_clock(),
+            backend,
+            codec: JsonCodec,
+            migrations: Migrations::new(),
+            max_age: None,
+            idle_timeout: None,
+            id_length: DEFAULT_ID_LENGTH,
         }
     }
+}
+
+impl<B, C> SessionContext<B, C>
+where
+    C: Codec,
+{
+    /// Whether a value expired
+    pub(crate) fn is_expired(&self, value: &Value) -> Result<bool, SessionError> {
+        value
+            .is_expired(self.clock.as_ref())
+            .map_err(SessionError::CheckExpired)
+    }
+
+    /// Encodes a value with the current version of its key
+    pub(crate) fn encode_value<T>(&self, key: &str, mut value: ValueRef<T>) -> Result<Vec<u8>, SessionError>
+    where
+        T: Serialize,
+    {
+        value.set_version(self.migrations.get_version(key));
+        value.encode(&self.codec).map_err(SessionError::EncodeValue)
+    }
+
+    /// Parses a value upgraded by migrations of its key
+    pub(crate) fn parse_value<O>(&self, key: &str, value: Value) -> Result<O, SessionError>
+    where
+        O: DeserializeOwned,
+    {
+        self.migrations
+            .apply(key, value, &self.codec)
+            .and_then(|value| value.into_parsed(&self.codec))
+            .map_err(SessionError::ParseValue)
+    }
+}
+
+impl<B, C> SessionManager<B, C>
+where
+    B: SessionBackend,
+    C: Codec,
+{
+    /// Creates a new session with a random ID
+    ///
+    /// ID is generated by a cryptographically secure random number generator
+    /// and contains only URL-safe characters.
+    pub async fn create_session(&self) -> Result<Session<B, C>, SessionError> {
+        loop {
+            let id = generate_id(self.context.id_length).map_err(SessionError::GenerateId)?;
+            let session = Session::new(id, self.context.clone());
+            if session.create().await? {
+                return Ok(session);
+            }
+        }
+    }
+
+    /// Returns an existing session for ID
+    ///
+    /// Returns `None` when session does not exist or expired,
+    /// or when ID could not be generated by [`SessionManager::create_session`],
+    /// so IDs received from clients never reach a backend unchecked.
+    pub async fn load_session<I>(&self, id: I) -> Result<Option<Session<B, C>>, SessionError>
+    where
+        I: Into<String>,
+    {
+        let id = id.into();
+        if !is_valid_id(&id, self.context.id_length) {
+            return Ok(None);
+        }
+        let session = Session::new(id, self.context.clone());
+        Ok(if session.exists().await? { Some(session) } else { None })
+    }
 
     /// Returns a session for ID
-    pub fn get_session<I>(&self, id: I) -> Session<B>
+    ///
+    /// Session is created on the first write, so any ID is accepted.
+    /// Do not pass IDs received from clients here,
+    /// use [`SessionManager::load_session`] and [`SessionManager::create_session`] instead.
+    ///
+    /// Operations on sessions with the same ID are serialized,
+    /// while different sessions are accessed concurrently.
+    pub fn get_session<I>(&self, id: I) -> Session<B, C>
     where
         I: Into<String>,
     {
-        Session::new(id, self.backend.clone())
+        Session::new(id, self.context.clone())
+    }
+}
+
+impl<B, C> Clone for SessionManager<B, C> {
+    fn clone(&self) -> Self {
+        Self {
+            context: self.context.clone(),
+        }
+    }
+}
+
+/// A builder for [`SessionManager`]
+pub struct SessionManagerBuilder<B, C = JsonCodec> {
+    backend: B,
+    codec: C,
+    migrations: Migrations,
+    clock: SharedClock,
+    max_age: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    id_length: usize,
+}
+
+impl<B, C> SessionManagerBuilder<B, C>
+where
+    B: SessionBackend,
+    C: Codec,
+{
+    /// Sets a codec for values
+    ///
+    /// Default is [`JsonCodec`].
+    /// Values written with other built-in codecs are still read when their features are enabled.
+    /// Migrations added before are kept.
+    pub fn codec<T>(self, codec: T) -> SessionManagerBuilder<B, T>
+    where
+        T: Codec,
+    {
+        SessionManagerBuilder {
+            backend: self.backend,
+            codec,
+            migrations: self.migrations,
+            clock: self.clock,
+            max_age: self.max_age,
+            idle_timeout: self.idle_timeout,
+            id_length: self.id_length,
+        }
+    }
+
+    /// Adds a migration for values of a key
+    ///
+    /// Values are stored with a version which equals to the number of migrations added for their key.
+    /// Migrations are applied in the order they are added, the first one upgrades values without a version,
+    /// so old values are upgraded before parsing.
+    /// Upgraded values are written on the next change.
+    ///
+    /// Values of a custom codec are converted into JSON before migration,
+    /// so the codec must be able to decode them as `serde_json::Value`.
+    ///
+    /// # Arguments
+    ///
+    /// * key - Key of values
+    /// * migration - Function which converts a value of the previous version into a value of the next one
+    pub fn migration<K, O, N, F>(mut self, key: K, migration: F) -> Self
+    where
+        K: Into<String>,
+        O: DeserializeOwned,
+        N: Serialize,
+        F: Fn(O) -> N + Send + Sync + 'static,
+    {
+        self.migrations.add(key.into(), migration);
+        self
+    }
+
+    /// Sets a clock for value expiration and session lifetime
+    ///
+    /// Default is the clock of a backend, see [`SessionBackend::get_clock`].
+    pub fn clock<T>(mut self, clock: T) -> Self
+    where
+        T: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets maximum age of a session
+    ///
+    /// A session older than given age is removed on the next access.
+    /// Session times are stored in seconds, so age is rounded up to a whole second.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets maximum time between accesses to a session
+    ///
+    /// A session which was not accessed for given time is removed on the next access.
+    /// Access time is tracked by a backend, see [`SessionBackend::get_session_access`].
+    /// Session times are stored in seconds, so timeout is rounded up to a whole second.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Sets length of IDs generated by [`SessionManager::create_session`]
+    ///
+    /// Every character contains 6 bits of entropy, default length is 32.
+    ///
+    /// # Panics
+    ///
+    /// Panics if length is less than 16.
+    pub fn id_length(mut self, id_length: usize) -> Self {
+        assert!(
+            id_length >= MIN_ID_LENGTH,
+            "session ID length must be at least {MIN_ID_LENGTH}"
+        );
+        self.id_length = id_length;
+        self
+    }
+
+    /// Creates a new session manager
+    pub fn build(self) -> SessionManager<B, C> {
+        SessionManager {
+            context: Arc::new(SessionContext {
+                backend: self.backend,
+                codec: self.codec,
+                migrations: self.migrations,
+                clock: self.clock,
+                locks: SessionLocks::new(),
+                max_age: self.max_age.map(duration_to_secs),
+                idle_timeout: self.idle_timeout.map(duration_to_secs),
+                id_length: self.id_length,
+            }),
+        }
     }
 }
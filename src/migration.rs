@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    codec::{Codec, CodecError},
+    value::Value,
+};
+
+type Migration = Box<dyn Fn(Value) -> Result<Value, CodecError> + Send + Sync>;
+
+/// Migrations of stored values grouped by key
+///
+/// Migration with index N upgrades a value of version N to version N + 1,
+/// so the current version of a key equals to the number of its migrations.
+/// Migrations do not depend on a codec, so a codec could be changed after they are added.
+pub(crate) struct Migrations {
+    items: HashMap<String, Vec<Migration>>,
+}
+
+impl Migrations {
+    pub(crate) fn new() -> Self {
+        Self { items: HashMap::new() }
+    }
+
+    /// Adds a migration to the next version of a key
+    pub(crate) fn add<O, N, F>(&mut self, key: String, migration: F)
+    where
+        O: DeserializeOwned,
+        N: Serialize,
+        F: Fn(O) -> N + Send + Sync + 'static,
+    {
+        self.items
+            .entry(key)
+            .or_default()
+            .push(Box::new(move |value| value.migrate(&migration)));
+    }
+
+    /// Returns the current version of values for a key
+    pub(crate) fn get_version(&self, key: &str) -> u32 {
+        self.items.get(key).map(|items| items.len() as u32).unwrap_or_default()
+    }
+
+    /// Upgrades a value to the current version
+    ///
+    /// Values of unknown newer versions are returned as is.
+    pub(crate) fn apply<C>(&self, key: &str, mut value: Value, codec: &C) -> Result<Value, CodecError>
+    where
+        C: Codec,
+    {
+        if let Some(items) = self.items.get(key)
+            && items.get(value.get_version() as usize).is_some()
+        {
+            value = value.into_portable(codec)?;
+            while let Some(migration) = items.get(value.get_version() as usize) {
+                value = migration(value)?;
+            }
+        }
+        Ok(value)
+    }
+}
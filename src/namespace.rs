@@ -0,0 +1,235 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    backend::SessionBackend,
+    codec::{Codec, JsonCodec},
+    session::{Session, SessionError},
+};
+
+/// Prefix of keys stored in namespaces
+///
+/// Prefix is reserved, so keys written directly to a session never get into a namespace.
+const PREFIX: &str = "__seance_ns";
+
+/// Separates a namespace name from a key
+///
+/// Unlike `:`, it is a valid file name character on all platforms.
+const SEPARATOR: char = '.';
+
+/// A view of a session which prefixes keys with a namespace name
+///
+/// Keys are stored as `__seance_ns.<namespace>.<key>`,
+/// so independent modules sharing a session do not collide on key names.
+/// Names of namespaces must be non-empty and consist of ASCII letters, digits, `-` and `_`,
+/// keys containing `.` are rejected with [`SessionError::InvalidKey`],
+/// so a key never collides with a key of a nested namespace.
+/// Migrations are registered for full keys.
+///
+/// Created by [`Session::namespace`].
+pub struct SessionNamespace<B, C = JsonCodec> {
+    session: Session<B, C>,
+    prefix: String,
+}
+
+impl<B, C> SessionNamespace<B, C>
+where
+    B: SessionBackend,
+    C: Codec,
+{
+    pub(crate) fn new(session: Session<B, C>, name: &str) -> Result<Self, SessionError> {
+        check_name(name)?;
+        Ok(Self {
+            session,
+            prefix: format!("{PREFIX}{SEPARATOR}{name}{SEPARATOR}"),
+        })
+    }
+
+    fn get_key<K>(&self, key: K) -> Result<String, SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let key = key.as_ref();
+        if key.contains(SEPARATOR) {
+            return Err(SessionError::InvalidKey(String::from(key)));
+        }
+        Ok(format!("{}{}", self.prefix, key))
+    }
+
+    /// Returns a nested namespace
+    ///
+    /// Returns [`SessionError::InvalidNamespace`] when name is empty
+    /// or contains characters other than ASCII letters, digits, `-` and `_`.
+    ///
+    /// # Arguments
+    ///
+    /// * name - Name of the namespace
+    pub fn namespace<N>(&self, name: N) -> Result<Self, SessionError>
+    where
+        N: AsRef<str>,
+    {
+        let name = name.as_ref();
+        check_name(name)?;
+        Ok(Self {
+            session: self.session.clone(),
+            prefix: format!("{}{name}{SEPARATOR}", self.prefix),
+        })
+    }
+
+    /// Sets a value for key
+    ///
+    /// See [`Session::set`].
+    pub async fn set<K, V>(&self, key: K, value: &V) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+        V: Serialize,
+    {
+        self.session.set(self.get_key(key)?, value).await
+    }
+
+    /// Sets a value for key which expires after given lifetime
+    ///
+    /// See [`Session::set_with_ttl`].
+    pub async fn set_with_ttl<K, V>(&self, key: K, value: &V, lifetime: Duration) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+        V: Serialize,
+    {
+        self.session.set_with_ttl(self.get_key(key)?, value, lifetime).await
+    }
+
+    /// Updates a value for key atomically
+    ///
+    /// See [`Session::update`].
+    pub async fn update<K, T, F>(&self, key: K, f: F) -> Result<Option<T>, SessionError>
+    where
+        K: AsRef<str>,
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Option<T>) -> Option<T>,
+    {
+        self.session.update(self.get_key(key)?, f).await
+    }
+
+    /// Increments a counter and returns its new value
+    ///
+    /// See [`Session::increment`].
+    pub async fn increment<K>(&self, key: K, delta: i64) -> Result<i64, SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.increment(self.get_key(key)?, delta).await
+    }
+
+    /// Gets a value for key
+    ///
+    /// See [`Session::get`].
+    pub async fn get<K, O>(&self, key: K) -> Result<Option<O>, SessionError>
+    where
+        K: AsRef<str>,
+        O: DeserializeOwned,
+    {
+        self.session.get(self.get_key(key)?).await
+    }
+
+    /// Returns the remaining lifetime of a value
+    ///
+    /// See [`Session::ttl`].
+    pub async fn ttl<K>(&self, key: K) -> Result<Option<Duration>, SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.ttl(self.get_key(key)?).await
+    }
+
+    /// Expires a key after given lifetime
+    ///
+    /// See [`Session::expire`].
+    pub async fn expire<K>(&self, key: K, lifetime: Duration) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.expire(self.get_key(key)?, lifetime).await
+    }
+
+    /// Expires a key at given time
+    ///
+    /// See [`Session::expire_at`].
+    pub async fn expire_at<K>(&self, key: K, expires_at: SystemTime) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.expire_at(self.get_key(key)?, expires_at).await
+    }
+
+    /// Removes expiration time of a key
+    ///
+    /// See [`Session::persist`].
+    pub async fn persist<K>(&self, key: K) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.persist(self.get_key(key)?).await
+    }
+
+    /// Removes a key
+    ///
+    /// See [`Session::remove`].
+    pub async fn remove<K>(&self, key: K) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        self.session.remove(self.get_key(key)?).await
+    }
+
+    /// Removes all keys of the namespace including nested namespaces
+    ///
+    /// Keys outside of the namespace are kept.
+    pub async fn clear(&self) -> Result<(), SessionError> {
+        let session = &self.session;
+        let _lock = session.lock.lock().await;
+        session.check_lifetime().await?;
+        let values = session
+            .context
+            .backend
+            .read_all_values(&session.id)
+            .await
+            .map_err(SessionError::backend)?;
+        let keys = Vec::from_iter(
+            values
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .filter(|key| key.starts_with(&self.prefix)),
+        );
+        if keys.is_empty() {
+            return Ok(());
+        }
+        session
+            .context
+            .backend
+            .remove_values(&session.id, &keys)
+            .await
+            .map_err(SessionError::backend)
+    }
+}
+
+impl<B, C> Clone for SessionNamespace<B, C> {
+    fn clone(&self) -> Self {
+        Self {
+            session: self.session.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+/// Rejects names which could contain a separator or are not safe in backend keys, e.g. file names
+fn check_name(name: &str) -> Result<(), SessionError> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|x| x.is_ascii_alphanumeric() || x == b'-' || x == b'_')
+    {
+        return Err(SessionError::InvalidNamespace(String::from(name)));
+    }
+    Ok(())
+}
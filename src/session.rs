@@ -1,119 +1,683 @@
-use std::{error::Error, fmt, sync::Arc, time::SystemTimeError};
+use std::{
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, SystemTimeError},
+};
 
+use getrandom::Error as RandomError;
 use serde::{Serialize, de::DeserializeOwned};
-use serde_json::Error as JsonError;
-use tokio::sync::Mutex;
 
 use crate::{
     backend::SessionBackend,
-    utils::{decode_value, encode_value},
-    value::{Value, ValueRef},
+    buffered::BufferedSession,
+    codec::{Codec, CodecError, JsonCodec},
+    flash::{FLASH_KEY, Flash},
+    key::SessionKey,
+    lock::SessionLock,
+    manager::SessionContext,
+    namespace::SessionNamespace,
+    utils::{generate_id, now, now_millis},
+    value::{Value, ValueRef, encode_counter},
 };
 
 /// Actual session
-#[derive(Clone)]
-pub struct Session<B> {
-    id: String,
-    backend: Arc<Mutex<B>>,
+///
+/// Keys starting with `__seance_` are reserved for data stored by the crate itself,
+/// e.g. flash messages are stored under `__seance_flash`.
+pub struct Session<B, C = JsonCodec> {
+    pub(crate) id: String,
+    pub(crate) context: Arc<SessionContext<B, C>>,
+    pub(crate) lock: SessionLock,
 }
 
-impl<B> Session<B>
+impl<B, C> Session<B, C>
 where
     B: SessionBackend,
+    C: Codec,
 {
-    pub(crate) fn new<I>(id: I, backend: Arc<Mutex<B>>) -> Self
+    pub(crate) fn new<I>(id: I, context: Arc<SessionContext<B, C>>) -> Self
     where
         I: Into<String>,
     {
-        Self { id: id.into(), backend }
+        let id = id.into();
+        let lock = context.locks.get(&id);
+        Self { id, context, lock }
     }
 
-    async fn read_value(&mut self, key: &str) -> Result<Option<Value>, SessionError> {
-        let mut backend = self.backend.lock().await;
-        match backend
-            .read_value(&self.id, key.as_ref())
+    /// Removes the session when it is older than max age or was idle for too long
+    ///
+    /// Must be called when holding a session lock.
+    pub(crate) async fn check_lifetime(&self) -> Result<(), SessionError> {
+        let SessionContext {
+            backend,
+            max_age,
+            idle_timeout,
+            ..
+        } = self.context.as_ref();
+        if max_age.is_none() && idle_timeout.is_none() {
+            return Ok(());
+        }
+        let Some((created, accessed)) = backend
+            .get_session_times(&self.id)
             .await
             .map_err(SessionError::backend)?
-        {
-            Some(value) => {
-                let value = decode_value(&value).map_err(SessionError::DecodeValue)?;
-                Ok(Some(value))
+        else {
+            return Ok(());
+        };
+        let timestamp = now(self.context.clock.as_ref()).map_err(SessionError::CheckExpired)?;
+        let is_expired = max_age.is_some_and(|max_age| timestamp.saturating_sub(created) >= max_age)
+            || idle_timeout.is_some_and(|idle_timeout| timestamp.saturating_sub(accessed) >= idle_timeout);
+        if is_expired {
+            backend.remove_session(&self.id).await.map_err(SessionError::backend)?;
+        }
+        Ok(())
+    }
+
+    /// Returns session ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Moves session data to a new random ID and removes the old session
+    ///
+    /// Call this method when privileges change, e.g. on login, to prevent session fixation.
+    /// Data is moved atomically when supported by a backend.
+    /// Clones of this session keep using the old ID.
+    /// Returns [`SessionError::SessionNotFound`] when the session does not exist or expired.
+    pub async fn regenerate_id(&mut self) -> Result<(), SessionError> {
+        let lock = self.lock.clone();
+        let _lock = lock.lock().await;
+        self.check_lifetime().await?;
+        loop {
+            let new_id = generate_id(self.context.id_length).map_err(SessionError::GenerateId)?;
+            let new_lock = self.context.locks.get(&new_id);
+            let is_renamed = {
+                let _new_lock = new_lock.lock().await;
+                self.context
+                    .backend
+                    .rename_session(&self.id, &new_id)
+                    .await
+                    .map_err(SessionError::backend)?
+            };
+            if is_renamed {
+                self.id = new_id;
+                self.lock = new_lock;
+                return Ok(());
+            }
+            if self
+                .context
+                .backend
+                .get_session_age(&self.id)
+                .await
+                .map_err(SessionError::backend)?
+                .is_none()
+            {
+                return Err(SessionError::SessionNotFound);
             }
-            None => Ok(None),
         }
     }
 
-    async fn write_value<V: Serialize>(&mut self, key: &str, value: V) -> Result<(), SessionError> {
-        let mut backend = self.backend.lock().await;
-        let data = encode_value(&value).map_err(SessionError::EncodeValue)?;
-        backend
-            .write_value(&self.id, key.as_ref(), &data)
+    /// Creates an empty session, returns `false` if session already exists
+    pub(crate) async fn create(&self) -> Result<bool, SessionError> {
+        let _lock = self.lock.lock().await;
+        self.context
+            .backend
+            .create_session(&self.id)
+            .await
+            .map_err(SessionError::backend)
+    }
+
+    /// Whether the session exists and is not expired
+    ///
+    /// A session exists once a value is written to it or it is created by
+    /// [`SessionManager::create_session`](crate::SessionManager::create_session).
+    pub async fn exists(&self) -> Result<bool, SessionError> {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let age = self
+            .context
+            .backend
+            .get_session_age(&self.id)
             .await
             .map_err(SessionError::backend)?;
-        Ok(())
+        Ok(age.is_some())
+    }
+
+    /// Returns the time when session was created or `None` if session does not exist
+    pub async fn created_at(&self) -> Result<Option<SystemTime>, SessionError> {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let age = self
+            .context
+            .backend
+            .get_session_age(&self.id)
+            .await
+            .map_err(SessionError::backend)?;
+        Ok(age.map(|age| SystemTime::UNIX_EPOCH + Duration::from_secs(age)))
+    }
+
+    /// Returns the remaining lifetime of a value
+    ///
+    /// Returns `None` if value does not exist, is expired or has no expiration time.
+    pub async fn ttl<K>(&self, key: K) -> Result<Option<Duration>, SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let Some(value) = self.read_value(key.as_ref()).await? else {
+            return Ok(None);
+        };
+        let Some(expires_at) = value.get_expires_at() else {
+            return Ok(None);
+        };
+        let timestamp = now_millis(self.context.clock.as_ref()).map_err(SessionError::CheckExpired)?;
+        if expires_at < timestamp {
+            return Ok(None);
+        }
+        Ok(Some(Duration::from_millis(expires_at - timestamp)))
+    }
+
+    /// Reads a value and removes it when expired
+    async fn read_value(&self, key: &str) -> Result<Option<Value>, SessionError> {
+        let Some(data) = self.read_raw_value(key).await? else {
+            return Ok(None);
+        };
+        let value = Value::decode(&data).map_err(SessionError::DecodeValue)?;
+        if self.context.is_expired(&value)? {
+            self.remove_expired_value(key, &data).await?;
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    /// Removes an expired value unless it was replaced by another process
+    async fn remove_expired_value(&self, key: &str, data: &[u8]) -> Result<(), SessionError> {
+        self.context
+            .backend
+            .compare_and_swap(&self.id, key, Some(data), None)
+            .await
+            .map(|_| ())
+            .map_err(SessionError::backend)
+    }
+
+    async fn read_raw_value(&self, key: &str) -> Result<Option<Vec<u8>>, SessionError> {
+        self.context
+            .backend
+            .read_value(&self.id, key)
+            .await
+            .map_err(SessionError::backend)
+    }
+
+    /// Replaces a value for key
+    ///
+    /// Function receives a current value and returns a new encoded one, `None` removes the value.
+    /// It is called again when the value was changed by another process.
+    /// Nothing is written when the value is unchanged.
+    async fn swap_value<F>(&self, key: &str, mut f: F) -> Result<(), SessionError>
+    where
+        F: FnMut(Option<Value>) -> Result<Option<Vec<u8>>, SessionError>,
+    {
+        loop {
+            let current = self.read_raw_value(key).await?;
+            let value = match current {
+                Some(ref value) => Some(Value::decode(value).map_err(SessionError::DecodeValue)?),
+                None => None,
+            };
+            let new = f(value)?;
+            if current == new {
+                return Ok(());
+            }
+            if self
+                .context
+                .backend
+                .compare_and_swap(&self.id, key, current.as_deref(), new.as_deref())
+                .await
+                .map_err(SessionError::backend)?
+            {
+                return Ok(());
+            }
+        }
     }
 
     /// Sets a value for key
-    pub async fn set<K, V>(&mut self, key: K, value: &V) -> Result<(), SessionError>
+    ///
+    /// Expiration time of an existing value is preserved,
+    /// use [`Session::set_with_ttl`] or [`Session::persist`] to change it.
+    /// Keys starting with `__seance_` are reserved, e.g. `__seance_flash` stores flash messages,
+    /// so do not use them for your own values.
+    pub async fn set<K, V>(&self, key: K, value: &V) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+        V: Serialize,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let key = key.as_ref();
+        self.swap_value(key, |old_value| {
+            let mut value = ValueRef::new(&value);
+            if let Some(old_value) = old_value
+                && !self.context.is_expired(&old_value)?
+                && let Some(expires_at) = old_value.get_expires_at()
+            {
+                value.set_expires_at(expires_at);
+            };
+            self.context.encode_value(key, value).map(Some)
+        })
+        .await
+    }
+
+    /// Sets a value for key which expires after given lifetime
+    ///
+    /// Unlike [`Session::set`] followed by [`Session::expire`],
+    /// the value and its expiration time are written at once.
+    pub async fn set_with_ttl<K, V>(&self, key: K, value: &V, lifetime: Duration) -> Result<(), SessionError>
     where
         K: AsRef<str>,
         V: Serialize,
     {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
         let key = key.as_ref();
         let mut value = ValueRef::new(&value);
-        if let Some(old_value) = self.read_value(key).await?
-            && !old_value.is_expired().map_err(SessionError::CheckExpired)?
-            && let Some(expires_at) = old_value.get_expires_at()
+        value
+            .set_lifetime(lifetime, self.context.clock.as_ref())
+            .map_err(SessionError::ExpireValue)?;
+        let value = self.context.encode_value(key, value)?;
+        self.context
+            .backend
+            .write_value(&self.id, key, &value)
+            .await
+            .map_err(SessionError::backend)
+    }
+
+    /// Updates a value for key atomically
+    ///
+    /// Function receives the current value and returns a new one, `None` removes the value.
+    /// It could be called several times when the value is changed concurrently by another process.
+    /// Expiration time of an existing value is preserved.
+    /// Updates are atomic only when a backend overrides [`SessionBackend::compare_and_swap`].
+    ///
+    /// Returns the new value.
+    pub async fn update<K, T, F>(&self, key: K, mut f: F) -> Result<Option<T>, SessionError>
+    where
+        K: AsRef<str>,
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Option<T>) -> Option<T>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let key = key.as_ref();
+        let mut result = None;
+        self.swap_value(key, |old_value| {
+            let (old_value, expires_at) = match old_value {
+                Some(old_value) if !self.context.is_expired(&old_value)? => {
+                    let expires_at = old_value.get_expires_at();
+                    let old_value = self.context.parse_value(key, old_value)?;
+                    (Some(old_value), expires_at)
+                }
+                _ => (None, None),
+            };
+            result = f(old_value);
+            match result {
+                Some(ref new_value) => {
+                    let mut value = ValueRef::new(new_value);
+                    if let Some(expires_at) = expires_at {
+                        value.set_expires_at(expires_at);
+                    }
+                    self.context.encode_value(key, value).map(Some)
+                }
+                None => Ok(None),
+            }
+        })
+        .await?;
+        Ok(result)
+    }
+
+    /// Increments a counter and returns its new value
+    ///
+    /// Counters are stored as plain integers, so backends are able to update them natively.
+    /// Counters of keys with migrations are stored with a version and updated with compare-and-swap.
+    /// A missing or expired value is treated as zero.
+    /// Expiration time of an existing value is preserved.
+    pub async fn increment<K>(&self, key: K, delta: i64) -> Result<i64, SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let key = key.as_ref();
+        let version = self.context.migrations.get_version(key);
+        if version == 0
+            && let Some(value) = self
+                .context
+                .backend
+                .increment_value(&self.id, key, delta)
+                .await
+                .map_err(SessionError::backend)?
         {
-            value.set_expires_at(expires_at);
-        };
-        self.write_value(key, value).await?;
-        Ok(())
+            return Ok(value);
+        }
+        // The value is wrapped, e.g. it has an expiration time or a version
+        let mut result = 0;
+        self.swap_value(key, |old_value| {
+            let (old_value, expires_at) = match old_value {
+                Some(old_value) if !self.context.is_expired(&old_value)? => {
+                    let expires_at = old_value.get_expires_at();
+                    let old_value = self.context.parse_value::<i64>(key, old_value)?;
+                    (old_value, expires_at)
+                }
+                _ => (0, None),
+            };
+            result = old_value.checked_add(delta).ok_or(SessionError::CounterOverflow)?;
+            if version == 0 && expires_at.is_none() {
+                return Ok(Some(encode_counter(result)));
+            }
+            let mut value = ValueRef::new(&result);
+            if let Some(expires_at) = expires_at {
+                value.set_expires_at(expires_at);
+            }
+            self.context.encode_value(key, value).map(Some)
+        })
+        .await?;
+        Ok(result)
     }
 
     /// Gets a value for key
-    pub async fn get<K, O>(&mut self, key: K) -> Result<Option<O>, SessionError>
+    ///
+    /// An expired value is removed from backend.
+    pub async fn get<K, O>(&self, key: K) -> Result<Option<O>, SessionError>
     where
         K: AsRef<str>,
         O: DeserializeOwned,
     {
-        Ok(
-            if let Some(value) = self.read_value(key.as_ref()).await.map_err(SessionError::backend)? {
-                if value.is_expired().map_err(SessionError::CheckExpired)? {
-                    None
-                } else {
-                    Some(value.into_parsed().map_err(SessionError::ParseValue)?)
-                }
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let key = key.as_ref();
+        match self.read_value(key).await? {
+            Some(value) => self.context.parse_value(key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a value for a typed key
+    pub async fn get_typed<T>(&self, key: &SessionKey<T>) -> Result<Option<T>, SessionError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get(key).await
+    }
+
+    /// Sets a value for a typed key
+    ///
+    /// Value expires after the key lifetime if it is set,
+    /// otherwise expiration time of an existing value is preserved.
+    pub async fn set_typed<T>(&self, key: &SessionKey<T>, value: &T) -> Result<(), SessionError>
+    where
+        T: Serialize,
+    {
+        match key.get_ttl() {
+            Some(ttl) => self.set_with_ttl(key, value, ttl).await,
+            None => self.set(key, value).await,
+        }
+    }
+
+    /// Gets values for several keys with a single backend call
+    ///
+    /// Returns values in the same order as keys.
+    /// Expired values are removed from backend with separate calls.
+    pub async fn get_many<K, O>(&self, keys: &[K]) -> Result<Vec<Option<O>>, SessionError>
+    where
+        K: AsRef<str>,
+        O: DeserializeOwned,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let keys = Vec::from_iter(keys.iter().map(AsRef::as_ref));
+        let values = self
+            .context
+            .backend
+            .read_values(&self.id, &keys)
+            .await
+            .map_err(SessionError::backend)?;
+        let mut result = Vec::with_capacity(values.len());
+        for (key, data) in keys.into_iter().zip(values) {
+            let Some(data) = data else {
+                result.push(None);
+                continue;
+            };
+            let value = Value::decode(&data).map_err(SessionError::DecodeValue)?;
+            if self.context.is_expired(&value)? {
+                self.remove_expired_value(key, &data).await?;
+                result.push(None);
             } else {
-                None
-            },
-        )
+                result.push(Some(self.context.parse_value(key, value)?));
+            }
+        }
+        Ok(result)
     }
 
-    /// Expires a key
-    pub async fn expire<K>(&mut self, key: K, seconds: u64) -> Result<(), SessionError>
+    /// Sets values for several keys with a single backend call
+    ///
+    /// Expiration time of existing values is preserved.
+    /// Unlike [`Session::set`], values changed by another process
+    /// between reading and writing are overwritten.
+    pub async fn set_many<K, V>(&self, values: &[(K, V)]) -> Result<(), SessionError>
     where
         K: AsRef<str>,
+        V: Serialize,
     {
-        let key = key.as_ref();
-        if let Some(mut value) = self.read_value(key).await.map_err(SessionError::backend)? {
-            value.set_lifetime(seconds).map_err(SessionError::ExpireValue)?;
-            self.write_value(key, value).await.map_err(SessionError::backend)?;
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let keys = Vec::from_iter(values.iter().map(|(key, _)| key.as_ref()));
+        let old_values = self
+            .context
+            .backend
+            .read_values(&self.id, &keys)
+            .await
+            .map_err(SessionError::backend)?;
+        let mut new_values = Vec::with_capacity(values.len());
+        for ((key, value), old_value) in values.iter().zip(old_values) {
+            let mut value = ValueRef::new(value);
+            if let Some(old_value) = old_value {
+                let old_value = Value::decode(&old_value).map_err(SessionError::DecodeValue)?;
+                if !self.context.is_expired(&old_value)?
+                    && let Some(expires_at) = old_value.get_expires_at()
+                {
+                    value.set_expires_at(expires_at);
+                }
+            }
+            new_values.push(self.context.encode_value(key.as_ref(), value)?);
         }
-        Ok(())
+        let new_values = Vec::from_iter(keys.into_iter().zip(new_values.iter().map(Vec::as_slice)));
+        self.context
+            .backend
+            .write_values(&self.id, &new_values)
+            .await
+            .map_err(SessionError::backend)
+    }
+
+    /// Expires a key after given lifetime
+    ///
+    /// Expiration time is stored with millisecond precision.
+    pub async fn expire<K>(&self, key: K, lifetime: Duration) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        self.swap_value(key.as_ref(), |value| match value {
+            Some(mut value) => {
+                value
+                    .set_lifetime(lifetime, self.context.clock.as_ref())
+                    .map_err(SessionError::ExpireValue)?;
+                value.encode().map(Some).map_err(SessionError::EncodeValue)
+            }
+            None => Ok(None),
+        })
+        .await
+    }
+
+    /// Expires a key at given time
+    pub async fn expire_at<K>(&self, key: K, expires_at: SystemTime) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        self.swap_value(key.as_ref(), |value| match value {
+            Some(mut value) => {
+                value.set_expires_at(expires_at).map_err(SessionError::ExpireValue)?;
+                value.encode().map(Some).map_err(SessionError::EncodeValue)
+            }
+            None => Ok(None),
+        })
+        .await
+    }
+
+    /// Removes expiration time of a key
+    ///
+    /// The value is kept until it is removed or the session ends.
+    /// Expired values are not restored.
+    pub async fn persist<K>(&self, key: K) -> Result<(), SessionError>
+    where
+        K: AsRef<str>,
+    {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        self.swap_value(key.as_ref(), |value| match value {
+            Some(mut value) => {
+                if !self.context.is_expired(&value)? {
+                    value.persist();
+                }
+                value.encode().map(Some).map_err(SessionError::EncodeValue)
+            }
+            None => Ok(None),
+        })
+        .await
     }
 
     /// Removes a key
-    pub async fn remove<K>(&mut self, key: K) -> Result<(), SessionError>
+    pub async fn remove<K>(&self, key: K) -> Result<(), SessionError>
     where
         K: AsRef<str>,
     {
-        let mut backend = self.backend.lock().await;
-        backend
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        self.context
+            .backend
             .remove_value(&self.id, key.as_ref())
             .await
             .map_err(SessionError::backend)
     }
+
+    /// Adds a flash message
+    ///
+    /// Messages are kept in `__seance_flash` key until they are read by [`Session::take_flashes`].
+    ///
+    /// # Arguments
+    ///
+    /// * kind - Kind of the message, e.g. `error` or `success`
+    /// * message - Text of the message
+    pub async fn flash<K, M>(&self, kind: K, message: M) -> Result<(), SessionError>
+    where
+        K: Into<String>,
+        M: Into<String>,
+    {
+        let flash = Flash::new(kind, message);
+        self.update(FLASH_KEY, |flashes: Option<Vec<Flash>>| {
+            let mut flashes = flashes.unwrap_or_default();
+            flashes.push(flash.clone());
+            Some(flashes)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Returns flash messages in the order they were added and removes them from session
+    pub async fn take_flashes(&self) -> Result<Vec<Flash>, SessionError> {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        let mut result = Vec::new();
+        self.swap_value(FLASH_KEY, |value| {
+            result = match value {
+                Some(value) if !self.context.is_expired(&value)? => self.context.parse_value(FLASH_KEY, value)?,
+                _ => Vec::new(),
+            };
+            Ok(None)
+        })
+        .await?;
+        Ok(result)
+    }
+
+    /// Refreshes session access time
+    ///
+    /// Backends refresh access time on every read and write,
+    /// use this method to keep a session alive without accessing values.
+    pub async fn touch(&self) -> Result<(), SessionError> {
+        let _lock = self.lock.lock().await;
+        self.check_lifetime().await?;
+        self.context
+            .backend
+            .touch_session(&self.id)
+            .await
+            .map_err(SessionError::backend)
+    }
+
+    /// Returns a view of the session which prefixes keys with a namespace name
+    ///
+    /// See [`SessionNamespace`] for details.
+    /// Returns [`SessionError::InvalidNamespace`] when name is empty
+    /// or contains characters other than ASCII letters, digits, `-` and `_`.
+    ///
+    /// # Arguments
+    ///
+    /// * name - Name of the namespace
+    pub fn namespace<N>(&self, name: N) -> Result<SessionNamespace<B, C>, SessionError>
+    where
+        N: AsRef<str>,
+    {
+        SessionNamespace::new(self.clone(), name.as_ref())
+    }
+
+    /// Converts session into a buffered one
+    ///
+    /// See [`BufferedSession`] for details.
+    pub fn into_buffered(self) -> BufferedSession<B, C>
+    where
+        B: 'static,
+        C: 'static,
+    {
+        BufferedSession::new(self)
+    }
+}
+
+/// Parses a value unless it is expired
+pub(crate) fn parse_value<B, C, O>(
+    context: &SessionContext<B, C>,
+    key: &str,
+    value: Value,
+) -> Result<Option<O>, SessionError>
+where
+    C: Codec,
+    O: DeserializeOwned,
+{
+    if context.is_expired(&value)? {
+        Ok(None)
+    } else {
+        context.parse_value(key, value).map(Some)
+    }
+}
+
+impl<B, C> Clone for Session<B, C> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            context: self.context.clone(),
+            lock: self.lock.clone(),
+        }
+    }
 }
 
 /// An error occurred in session
@@ -123,18 +687,28 @@ pub enum SessionError {
     Backend(Box<dyn Error + Send + Sync>),
     /// Failed to check whether value expired
     CheckExpired(SystemTimeError),
+    /// Counter value does not fit into `i64`
+    CounterOverflow,
     /// Failed to decode value
-    DecodeValue(JsonError),
+    DecodeValue(CodecError),
     /// Failed to encode value
-    EncodeValue(JsonError),
+    EncodeValue(CodecError),
     /// Failed to expire value
     ExpireValue(SystemTimeError),
+    /// Failed to generate session ID
+    GenerateId(RandomError),
+    /// Key of a namespace contains a separator
+    InvalidKey(String),
+    /// Namespace name is empty or contains unsupported characters
+    InvalidNamespace(String),
     /// Failed to parse value
-    ParseValue(JsonError),
+    ParseValue(CodecError),
+    /// Session does not exist
+    SessionNotFound,
 }
 
 impl SessionError {
-    fn backend<E: Error + Send + Sync + 'static>(err: E) -> Self {
+    pub(crate) fn backend<E: Error + Send + Sync + 'static>(err: E) -> Self {
         Self::Backend(Box::new(err))
     }
 }
@@ -144,10 +718,15 @@ impl Error for SessionError {
         match self {
             SessionError::Backend(err) => Some(err.as_ref()),
             SessionError::CheckExpired(err) => Some(err),
+            SessionError::CounterOverflow => None,
             SessionError::DecodeValue(err) => Some(err),
             SessionError::EncodeValue(err) => Some(err),
             SessionError::ExpireValue(err) => Some(err),
+            SessionError::GenerateId(err) => Some(err),
+            SessionError::InvalidKey(_) => None,
+            SessionError::InvalidNamespace(_) => None,
             SessionError::ParseValue(err) => Some(err),
+            SessionError::SessionNotFound => None,
         }
     }
 }
@@ -159,10 +738,15 @@ impl fmt::Display for SessionError {
             SessionError::CheckExpired(err) => {
                 write!(out, "failed to check whether value expired: {err}")
             }
+            SessionError::CounterOverflow => write!(out, "counter overflow"),
             SessionError::DecodeValue(err) => write!(out, "failed to decode value: {err}"),
             SessionError::EncodeValue(err) => write!(out, "failed to encode value: {err}"),
             SessionError::ExpireValue(err) => write!(out, "failed to expire value: {err}"),
+            SessionError::GenerateId(err) => write!(out, "failed to generate session ID: {err}"),
+            SessionError::InvalidKey(key) => write!(out, "invalid key: {key:?}"),
+            SessionError::InvalidNamespace(name) => write!(out, "invalid namespace name: {name:?}"),
             SessionError::ParseValue(err) => write!(out, "failed to parse value: {err}"),
+            SessionError::SessionNotFound => write!(out, "session does not exist"),
         }
     }
 }
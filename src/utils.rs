@@ -1,17 +1,72 @@
-use serde::{Serialize, de::DeserializeOwned};
-use serde_json::Error as JsonError;
-use std::time::{SystemTime, SystemTimeError};
+use getrandom::Error as RandomError;
 
-pub(crate) fn now() -> Result<u64, SystemTimeError> {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|x| x.as_secs())
+use crate::clock::Clock;
+use std::time::{Duration, SystemTime, SystemTimeError};
+
+/// URL-safe base64 alphabet
+const ID_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+pub(crate) fn now(clock: &dyn Clock) -> Result<u64, SystemTimeError> {
+    clock.now().duration_since(SystemTime::UNIX_EPOCH).map(|x| x.as_secs())
+}
+
+pub(crate) fn now_millis(clock: &dyn Clock) -> Result<u64, SystemTimeError> {
+    time_to_millis(clock.now())
+}
+
+/// Converts time into UNIX timestamp in milliseconds
+pub(crate) fn time_to_millis(time: SystemTime) -> Result<u64, SystemTimeError> {
+    time.duration_since(SystemTime::UNIX_EPOCH).map(duration_to_millis)
+}
+
+/// Converts duration into milliseconds saturating at `u64::MAX`
+pub(crate) fn duration_to_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Converts duration into whole seconds rounding up
+pub(crate) fn duration_to_secs(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_add(u64::from(duration.subsec_nanos() > 0))
 }
 
-pub(super) fn encode_value<V: Serialize>(value: &V) -> Result<Vec<u8>, JsonError> {
-    serde_json::to_vec(value)
+/// Generates a random URL-safe ID, every character contains 6 bits of entropy
+pub(crate) fn generate_id(length: usize) -> Result<String, RandomError> {
+    let mut data = vec![0; length];
+    getrandom::fill(&mut data)?;
+    Ok(String::from_iter(
+        data.into_iter().map(|x| char::from(ID_ALPHABET[usize::from(x & 63)])),
+    ))
 }
 
-pub(super) fn decode_value<V: DeserializeOwned>(value: &[u8]) -> Result<V, JsonError> {
-    serde_json::from_slice(value)
+/// Whether an ID could be generated by [`generate_id`] with given length
+pub(crate) fn is_valid_id(id: &str, length: usize) -> bool {
+    id.len() == length && id.bytes().all(|x| ID_ALPHABET.contains(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_unique_id() {
+        let id = generate_id(32).unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|x| ID_ALPHABET.contains(&x)));
+        assert!(is_valid_id(&id, 32));
+        assert!(!is_valid_id(&id, 16));
+        assert!(!is_valid_id(&format!("{}/", &id[1..]), 32));
+        assert_ne!(id, generate_id(32).unwrap());
+        assert!(generate_id(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn round_up_secs() {
+        assert_eq!(duration_to_secs(Duration::from_secs(3)), 3);
+        assert_eq!(duration_to_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(duration_to_secs(Duration::from_nanos(1)), 1);
+        assert_eq!(duration_to_secs(Duration::ZERO), 0);
+        assert_eq!(duration_to_secs(Duration::MAX), u64::MAX);
+    }
 }
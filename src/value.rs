@@ -1,35 +1,238 @@
-use crate::utils::now;
+use crate::{
+    clock::Clock,
+    codec::{Codec, CodecError, JsonCodec, decode_builtin, is_builtin},
+    utils::{duration_to_millis, now_millis, time_to_millis},
+};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use serde_json::{Error as JsonError, Value as JsonValue};
-use std::time::SystemTimeError;
+use serde_json::Value as JsonValue;
+use std::time::{Duration, SystemTime, SystemTimeError};
 
 /// Wrapper for an owned session value
-#[derive(Serialize, Deserialize)]
+///
+/// JSON values are stored as `{"expires_at":...,"expires_at_ms":...,"value":...,"version":...}` objects,
+/// `expires_at` in seconds is kept for values written by previous versions of the crate.
+/// Values of other codecs are stored as a format tag, a flags byte,
+/// an optional big-endian expiration timestamp in milliseconds, an optional big-endian version and encoded data.
 pub struct Value {
+    /// UNIX timestamp in milliseconds
     expires_at: Option<u64>,
+    version: u32,
+    data: ValueData,
+}
+
+/// Binary value contains an expiration timestamp
+const FLAG_EXPIRES_AT: u8 = 0b01;
+/// Binary value contains a version
+const FLAG_VERSION: u8 = 0b10;
+
+/// Data of a stored value
+enum ValueData {
+    /// A JSON value or a counter
+    Json(JsonValue),
+    /// A value encoded by a codec with the format tag
+    Encoded { tag: u8, data: Vec<u8> },
+}
+
+/// A stored JSON value
+#[derive(Deserialize)]
+struct JsonEnvelope {
+    expires_at: Option<u64>,
+    expires_at_ms: Option<u64>,
     value: JsonValue,
+    #[serde(default)]
+    version: u32,
+}
+
+/// A JSON value to store
+#[derive(Serialize)]
+struct JsonEnvelopeRef<'a, T>
+where
+    T: Serialize + ?Sized,
+{
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at_ms: Option<u64>,
+    value: &'a T,
+    #[serde(skip_serializing_if = "is_initial_version")]
+    version: u32,
+}
+
+impl<'a, T> JsonEnvelopeRef<'a, T>
+where
+    T: Serialize + ?Sized,
+{
+    fn new(expires_at: Option<u64>, value: &'a T, version: u32) -> Self {
+        Self {
+            expires_at: expires_at.map(|expires_at| expires_at / 1000),
+            expires_at_ms: expires_at,
+            value,
+            version,
+        }
+    }
 }
 
 impl Value {
+    /// Decodes a stored value
+    ///
+    /// Counters are stored as plain integers without a wrapper.
+    /// Data is not decoded until [`Value::into_parsed`] is called,
+    /// so expiration time is available for values of any format.
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, CodecError> {
+        if let Some(counter) = decode_counter(data) {
+            return Ok(Self {
+                expires_at: None,
+                version: 0,
+                data: ValueData::Json(JsonValue::from(counter)),
+            });
+        }
+        let (&tag, rest) = data.split_first().ok_or(CodecError::Truncated)?;
+        if tag == JsonCodec::TAG {
+            let JsonEnvelope {
+                expires_at,
+                expires_at_ms,
+                value,
+                version,
+            } = JsonCodec.decode(data).map_err(CodecError::codec)?;
+            // Values with expiration time in seconds expire after the end of the second
+            let expires_at = expires_at_ms.or(expires_at.map(|x| x.saturating_mul(1000).saturating_add(999)));
+            return Ok(Self {
+                expires_at,
+                version,
+                data: ValueData::Json(value),
+            });
+        }
+        let (&flags, rest) = rest.split_first().ok_or(CodecError::Truncated)?;
+        let (expires_at, rest) = if flags & FLAG_EXPIRES_AT == 0 {
+            (None, rest)
+        } else {
+            let (timestamp, rest) = rest.split_first_chunk().ok_or(CodecError::Truncated)?;
+            (Some(u64::from_be_bytes(*timestamp)), rest)
+        };
+        let (version, rest) = if flags & FLAG_VERSION == 0 {
+            (0, rest)
+        } else {
+            let (version, rest) = rest.split_first_chunk().ok_or(CodecError::Truncated)?;
+            (u32::from_be_bytes(*version), rest)
+        };
+        Ok(Self {
+            expires_at,
+            version,
+            data: ValueData::Encoded {
+                tag,
+                data: rest.to_vec(),
+            },
+        })
+    }
+
+    /// Encodes a value for storing
+    ///
+    /// Data is kept in its original format.
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        match &self.data {
+            ValueData::Json(value) => {
+                let value = JsonEnvelopeRef::new(self.expires_at, value, self.version);
+                JsonCodec.encode(&value).map_err(CodecError::codec)
+            }
+            ValueData::Encoded { tag, data } => Ok(encode_envelope(*tag, self.expires_at, self.version, data)),
+        }
+    }
+
     /// Returns a parsed value
-    pub fn into_parsed<T: DeserializeOwned>(mut self) -> Result<T, JsonError> {
-        serde_json::from_value(self.value.take())
+    ///
+    /// Values written by other built-in codecs are parsed when their features are enabled.
+    pub fn into_parsed<C, T>(self, codec: &C) -> Result<T, CodecError>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        match self.data {
+            ValueData::Encoded { tag, data } if tag == C::TAG => codec.decode(&data).map_err(CodecError::codec),
+            data => Self { data, ..self }.into_parsed_portable(),
+        }
+    }
+
+    /// Returns a parsed value of JSON or a built-in format
+    fn into_parsed_portable<T>(self) -> Result<T, CodecError>
+    where
+        T: DeserializeOwned,
+    {
+        match self.data {
+            ValueData::Json(value) => serde_json::from_value(value).map_err(CodecError::codec),
+            ValueData::Encoded { tag, data } => {
+                decode_builtin(tag, &data).unwrap_or(Err(CodecError::UnknownFormat(tag)))
+            }
+        }
+    }
+
+    /// Converts data of a custom codec into JSON
+    ///
+    /// JSON and built-in formats are parsed without a codec,
+    /// so migrations do not depend on a codec of a manager.
+    pub(crate) fn into_portable<C>(self, codec: &C) -> Result<Self, CodecError>
+    where
+        C: Codec,
+    {
+        match self.data {
+            ValueData::Encoded { tag, data } if tag == C::TAG && !is_builtin(tag) => Ok(Self {
+                expires_at: self.expires_at,
+                version: self.version,
+                data: ValueData::Json(codec.decode(&data).map_err(CodecError::codec)?),
+            }),
+            data => Ok(Self { data, ..self }),
+        }
+    }
+
+    /// Upgrades a value to the next version
+    ///
+    /// Migrated data is kept as JSON until the value is written again.
+    /// Data of custom codecs must be converted by [`Value::into_portable`] first.
+    pub(crate) fn migrate<O, N, F>(self, migration: F) -> Result<Self, CodecError>
+    where
+        O: DeserializeOwned,
+        N: Serialize,
+        F: FnOnce(O) -> N,
+    {
+        let expires_at = self.expires_at;
+        let version = self.version + 1;
+        let value = migration(self.into_parsed_portable()?);
+        let value = serde_json::to_value(&value).map_err(CodecError::codec)?;
+        Ok(Self {
+            expires_at,
+            version,
+            data: ValueData::Json(value),
+        })
+    }
+
+    /// Returns version of a value
+    pub fn get_version(&self) -> u32 {
+        self.version
     }
 
-    /// Sets value lifetime in seconds from now
-    pub fn set_lifetime(&mut self, lifetime: u64) -> Result<(), SystemTimeError> {
-        self.expires_at = Some(now()? + lifetime);
+    /// Sets value lifetime from now
+    pub fn set_lifetime(&mut self, lifetime: Duration, clock: &dyn Clock) -> Result<(), SystemTimeError> {
+        self.expires_at = Some(now_millis(clock)?.saturating_add(duration_to_millis(lifetime)));
         Ok(())
     }
 
-    /// Returns UNIX timestamp when the value expires
+    /// Sets time when the value expires
+    pub fn set_expires_at(&mut self, expires_at: SystemTime) -> Result<(), SystemTimeError> {
+        self.expires_at = Some(time_to_millis(expires_at)?);
+        Ok(())
+    }
+
+    /// Removes expiration time, so the value is kept until the session ends
+    pub fn persist(&mut self) {
+        self.expires_at = None;
+    }
+
+    /// Returns UNIX timestamp in milliseconds when the value expires
     pub fn get_expires_at(&self) -> Option<u64> {
         self.expires_at
     }
 
     /// Whether value expired
-    pub fn is_expired(&self) -> Result<bool, SystemTimeError> {
-        let timestamp = now()?;
+    pub fn is_expired(&self, clock: &dyn Clock) -> Result<bool, SystemTimeError> {
+        let timestamp = now_millis(clock)?;
         Ok(self
             .expires_at
             .map(|expires_at| expires_at < timestamp)
@@ -38,13 +241,14 @@ impl Value {
 }
 
 /// Wrapper for a session value reference
-#[derive(Serialize)]
 pub struct ValueRef<'a, T>
 where
     T: Serialize,
 {
+    /// UNIX timestamp in milliseconds
     expires_at: Option<u64>,
     value: &'a T,
+    version: u32,
 }
 
 impl<'a, T> ValueRef<'a, T>
@@ -56,73 +260,266 @@ where
         Self {
             value,
             expires_at: None,
+            version: 0,
         }
     }
 
-    /// Sets UNIX timestamp when a value should expire
+    /// Sets UNIX timestamp in milliseconds when a value should expire
     pub fn set_expires_at(&mut self, expires_at: u64) {
         self.expires_at = Some(expires_at);
     }
+
+    /// Sets value lifetime from now
+    pub fn set_lifetime(&mut self, lifetime: Duration, clock: &dyn Clock) -> Result<(), SystemTimeError> {
+        self.expires_at = Some(now_millis(clock)?.saturating_add(duration_to_millis(lifetime)));
+        Ok(())
+    }
+
+    /// Sets version of a value
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    /// Encodes a value for storing
+    pub(crate) fn encode<C>(&self, codec: &C) -> Result<Vec<u8>, CodecError>
+    where
+        C: Codec,
+    {
+        if C::TAG == JsonCodec::TAG {
+            let value = JsonEnvelopeRef::new(self.expires_at, self.value, self.version);
+            return codec.encode(&value).map_err(CodecError::codec);
+        }
+        let data = codec.encode(self.value).map_err(CodecError::codec)?;
+        Ok(encode_envelope(C::TAG, self.expires_at, self.version, &data))
+    }
+}
+
+fn is_initial_version(version: &u32) -> bool {
+    *version == 0
+}
+
+/// Prepends encoded data with a format tag, an expiration time and a version
+fn encode_envelope(tag: u8, expires_at: Option<u64>, version: u32, data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + 14);
+    result.push(tag);
+    let mut flags = 0;
+    if expires_at.is_some() {
+        flags |= FLAG_EXPIRES_AT;
+    }
+    if version != 0 {
+        flags |= FLAG_VERSION;
+    }
+    result.push(flags);
+    if let Some(expires_at) = expires_at {
+        result.extend_from_slice(&expires_at.to_be_bytes());
+    }
+    if version != 0 {
+        result.extend_from_slice(&version.to_be_bytes());
+    }
+    result.extend_from_slice(data);
+    result
+}
+
+/// Encodes a counter value
+///
+/// Backends are able to increment counters natively, e.g. using HINCRBY in redis.
+pub(crate) fn encode_counter(value: i64) -> Vec<u8> {
+    value.to_string().into_bytes()
+}
+
+/// Decodes a counter value
+///
+/// Returns `None` if data is not an integer in the counter encoding.
+pub(crate) fn decode_counter(data: &[u8]) -> Option<i64> {
+    let digits = data.strip_prefix(b"-").unwrap_or(data);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(data).ok()?.parse().ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::SystemClock;
 
     #[test]
-    fn deserialize_value() {
-        let value: Value = serde_json::from_value(serde_json::json!({
-            "expires_at": 0,
-            "value": "test",
-        }))
-        .unwrap();
-        assert_eq!(value.get_expires_at().unwrap(), 0);
-        assert!(value.is_expired().unwrap());
-        assert_eq!(value.into_parsed::<String>().unwrap(), "test");
-
-        let value: Value = serde_json::from_value(serde_json::json!({
-            "value": "test",
-        }))
-        .unwrap();
+    fn decode_value() {
+        let value = Value::decode(br#"{"expires_at":0,"value":"test"}"#).unwrap();
+        assert_eq!(value.get_expires_at().unwrap(), 999);
+        assert!(value.is_expired(&SystemClock).unwrap());
+        assert_eq!(value.into_parsed::<_, String>(&JsonCodec).unwrap(), "test");
+
+        let value = Value::decode(br#"{"expires_at":0,"expires_at_ms":500,"value":"test"}"#).unwrap();
+        assert_eq!(value.get_expires_at().unwrap(), 500);
+        assert!(value.is_expired(&SystemClock).unwrap());
+        assert_eq!(value.into_parsed::<_, String>(&JsonCodec).unwrap(), "test");
+
+        let value = Value::decode(br#"{"value":"test"}"#).unwrap();
+        assert!(value.get_expires_at().is_none());
+        assert!(!value.is_expired(&SystemClock).unwrap());
+        assert_eq!(value.into_parsed::<_, String>(&JsonCodec).unwrap(), "test");
+
+        assert!(matches!(Value::decode(b""), Err(CodecError::Truncated)));
+        assert!(matches!(Value::decode(&[0x7F]), Err(CodecError::Truncated)));
+        assert!(matches!(Value::decode(&[0x7F, 1, 0]), Err(CodecError::Truncated)));
+        let value = Value::decode(&[0x7F, 1, 0, 0, 0, 0, 0, 0, 0, 100, 1]).unwrap();
+        assert_eq!(value.get_expires_at(), Some(100));
+        assert!(matches!(
+            value.into_parsed::<_, u8>(&JsonCodec),
+            Err(CodecError::UnknownFormat(0x7F))
+        ));
+    }
+
+    #[test]
+    fn encode_value() {
+        let mut value = Value::decode(br#"{"expires_at":0,"value":"test"}"#).unwrap();
+        value.set_lifetime(Duration::from_millis(100), &SystemClock).unwrap();
+        let timestamp = now_millis(&SystemClock).unwrap();
+        assert!(value.get_expires_at().unwrap() > timestamp);
+        assert!(value.get_expires_at().unwrap() <= timestamp + 100);
+        assert!(!value.is_expired(&SystemClock).unwrap());
+        value
+            .set_expires_at(SystemTime::UNIX_EPOCH + Duration::from_millis(100_500))
+            .unwrap();
+        assert_eq!(value.get_expires_at().unwrap(), 100_500);
+        assert_eq!(
+            value.encode().unwrap(),
+            br#"{"expires_at":100,"expires_at_ms":100500,"value":"test"}"#
+        );
+        let mut value = Value::decode(br#"{"expires_at":100,"value":"test"}"#).unwrap();
+        assert_eq!(
+            value.encode().unwrap(),
+            br#"{"expires_at":100,"expires_at_ms":100999,"value":"test"}"#
+        );
+        value.persist();
+        assert_eq!(value.encode().unwrap(), br#"{"expires_at":null,"value":"test"}"#);
+
+        let value = Value::decode(br#"{"value":"test"}"#).unwrap();
+        assert_eq!(value.encode().unwrap(), br#"{"expires_at":null,"value":"test"}"#);
+
+        let mut value = Value::decode(&[0x7F, 0, 1, 2]).unwrap();
         assert!(value.get_expires_at().is_none());
-        assert!(!value.is_expired().unwrap());
-        assert_eq!(value.into_parsed::<String>().unwrap(), "test");
+        assert_eq!(value.encode().unwrap(), [0x7F, 0, 1, 2]);
+        value.expires_at = Some(258);
+        assert_eq!(value.encode().unwrap(), [0x7F, 1, 0, 0, 0, 0, 0, 0, 1, 2, 1, 2]);
     }
 
     #[test]
-    fn serialize_value() {
-        let mut value: Value = serde_json::from_value(serde_json::json!({
-            "expires_at": 0,
-            "value": "test",
-        }))
-        .unwrap();
-        value.set_lifetime(100).unwrap();
-        assert!(value.get_expires_at().unwrap() > now().unwrap());
-        value.expires_at = Some(100);
-        assert_eq!(value.get_expires_at().unwrap(), 100);
+    fn value_version() {
+        let value = Value::decode(br#"{"expires_at":null,"value":"test","version":2}"#).unwrap();
+        assert_eq!(value.get_version(), 2);
         assert_eq!(
-            serde_json::to_string(&value).unwrap(),
-            r#"{"expires_at":100,"value":"test"}"#
+            value.encode().unwrap(),
+            br#"{"expires_at":null,"value":"test","version":2}"#
         );
+        assert_eq!(Value::decode(br#"{"value":"test"}"#).unwrap().get_version(), 0);
+
+        let value = Value::decode(&[0x7F, 2, 0, 0, 0, 3, 1]).unwrap();
+        assert!(value.get_expires_at().is_none());
+        assert_eq!(value.get_version(), 3);
+        assert_eq!(value.encode().unwrap(), [0x7F, 2, 0, 0, 0, 3, 1]);
+        let value = Value::decode(&[0x7F, 3, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 3, 1]).unwrap();
+        assert_eq!(value.get_expires_at(), Some(100));
+        assert_eq!(value.get_version(), 3);
+        assert!(matches!(Value::decode(&[0x7F, 2, 0, 0]), Err(CodecError::Truncated)));
+
+        let value = Value::decode(br#"{"value":2}"#).unwrap();
+        let value = value.migrate(|x: u32| x.to_string()).unwrap();
+        assert_eq!(value.get_version(), 1);
+        assert_eq!(value.into_parsed::<_, String>(&JsonCodec).unwrap(), "2");
 
-        let value: Value = serde_json::from_value(serde_json::json!({"value": "test"})).unwrap();
+        let mut value = ValueRef::new(&"test");
+        value.set_version(1);
         assert_eq!(
-            serde_json::to_string(&value).unwrap(),
-            r#"{"expires_at":null,"value":"test"}"#
+            value.encode(&JsonCodec).unwrap(),
+            br#"{"expires_at":null,"value":"test","version":1}"#
         );
     }
 
     #[test]
-    fn serialize_value_ref() {
+    fn encode_value_ref() {
         let mut value = ValueRef::new(&"testref");
         assert_eq!(
-            serde_json::to_string(&value).unwrap(),
-            r#"{"expires_at":null,"value":"testref"}"#
+            value.encode(&JsonCodec).unwrap(),
+            br#"{"expires_at":null,"value":"testref"}"#
+        );
+        value.set_expires_at(100_000);
+        assert_eq!(
+            value.encode(&JsonCodec).unwrap(),
+            br#"{"expires_at":100,"expires_at_ms":100000,"value":"testref"}"#
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_value() {
+        use crate::codec::MessagePackCodec;
+        check_codec(MessagePackCodec);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_value() {
+        use crate::codec::CborCodec;
+        check_codec(CborCodec);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_value() {
+        use crate::codec::BincodeCodec;
+        check_codec(BincodeCodec);
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor", feature = "bincode"))]
+    fn check_codec<C: Codec>(codec: C) {
+        let data = (String::from("test"), vec![1u8, 2, 3]);
+        let mut value = ValueRef::new(&data);
+        let data = value.encode(&codec).unwrap();
+        assert_eq!(data[..2], [C::TAG, 0]);
+        let decoded = Value::decode(&data).unwrap();
+        assert!(decoded.get_expires_at().is_none());
+        assert_eq!(
+            decoded.into_parsed::<_, (String, Vec<u8>)>(&codec).unwrap(),
+            (String::from("test"), vec![1, 2, 3])
         );
+
         value.set_expires_at(100);
+        let data = value.encode(&codec).unwrap();
+        let decoded = Value::decode(&data).unwrap();
+        assert_eq!(decoded.get_expires_at(), Some(100));
+        assert_eq!(decoded.encode().unwrap(), data);
+        // Values are parsed by a built-in codec for their tag
+        let decoded = Value::decode(&data).unwrap();
         assert_eq!(
-            serde_json::to_string(&value).unwrap(),
-            r#"{"expires_at":100,"value":"testref"}"#
+            decoded.into_parsed::<_, (String, Vec<u8>)>(&JsonCodec).unwrap().0,
+            "test"
         );
     }
+
+    #[test]
+    fn counter() {
+        assert_eq!(encode_counter(-12), b"-12");
+        assert_eq!(decode_counter(b"-12"), Some(-12));
+        assert_eq!(decode_counter(b"0"), Some(0));
+        assert_eq!(decode_counter(b"9223372036854775807"), Some(i64::MAX));
+        for data in [
+            &b""[..],
+            b"-",
+            b"+1",
+            b" 1",
+            b"1.5",
+            b"9223372036854775808",
+            br#"{"value":1}"#,
+        ] {
+            assert_eq!(decode_counter(data), None);
+        }
+
+        let value = Value::decode(b"5").unwrap();
+        assert!(value.get_expires_at().is_none());
+        assert_eq!(value.into_parsed::<_, i64>(&JsonCodec).unwrap(), 5);
+        let value = Value::decode(br#"{"expires_at":null,"value":5}"#).unwrap();
+        assert_eq!(value.into_parsed::<_, i64>(&JsonCodec).unwrap(), 5);
+    }
 }
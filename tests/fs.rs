@@ -1,34 +1,1096 @@
 use std::time::Duration;
 
-use tempfile::tempdir;
+use serde::{Deserialize, Serialize};
+use tempfile::{TempDir, tempdir};
 use tokio::time::sleep;
 
-use seance::{SessionCollector, SessionManager, backend::fs::FilesystemBackend};
+use seance::{
+    Clock, CollectorMode, Flash, ManualClock, SessionCollector, SessionError, SessionKey, SessionManager,
+    backend::{
+        SessionBackend,
+        fs::{FilesystemBackend, FilesystemBackendError, FilesystemStorage, MarkerRecovery},
+    },
+    codec::JsonCodec,
+};
 
-#[tokio::test]
-async fn fs() {
+async fn backend(storage: FilesystemStorage, clock: &ManualClock) -> (TempDir, FilesystemBackend) {
     let tmpdir = tempdir().expect("Failed to create temp directory");
-    let backend = FilesystemBackend::new(tmpdir.keep());
-    let manager = SessionManager::new(backend.clone());
-    let mut session = manager.get_session("session-id");
+    let backend = FilesystemBackend::builder(tmpdir.path())
+        .storage(storage)
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap();
+    (tmpdir, backend)
+}
+
+async fn run(backend: FilesystemBackend, clock: ManualClock) {
+    let manager = SessionManager::builder(backend.clone()).clock(clock.clone()).build();
+    let session = manager.get_session("session-id");
     session.set("key", &"value").await.unwrap();
     assert_eq!("value", session.get::<_, String>("key").await.unwrap().unwrap());
     session.remove("key").await.unwrap();
     assert!(session.get::<_, String>("key").await.unwrap().is_none());
     session.set("key", &"value").await.unwrap();
-    session.expire("key", 1).await.unwrap();
-    sleep(Duration::from_secs(2)).await;
+    session.expire("key", Duration::from_millis(300)).await.unwrap();
+    clock.advance(Duration::from_millis(100));
+    assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    clock.advance(Duration::from_millis(300));
     assert!(session.get::<_, String>("key").await.unwrap().is_none());
 
-    let gc_period = Duration::from_secs(1);
-    let session_lifetime = Duration::from_secs(1);
+    let gc_period = Duration::from_millis(10);
+    let session_lifetime = Duration::from_secs(3);
+    // Collector uses the clock of a backend
     let mut collector = SessionCollector::new(backend, gc_period, session_lifetime);
-    let handle = collector.get_handle();
     session.set("key", &"value").await.unwrap();
-    tokio::spawn(async move {
+    collector.collect_once().await;
+    assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    clock.advance(Duration::from_secs(5));
+    collector.collect_once().await;
+    assert!(session.get::<_, String>("key").await.unwrap().is_none());
+
+    let handle = collector.get_handle();
+    let task = tokio::spawn(async move {
         collector.run().await;
     });
-    sleep(Duration::from_secs(2)).await;
     handle.shutdown().await;
-    assert!(session.get::<_, String>("key").await.unwrap().is_none());
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn fs() {
+    let clock = ManualClock::default();
+    let (_tmpdir, backend) = backend(FilesystemStorage::Directory, &clock).await;
+    run(backend, clock).await;
+}
+
+#[tokio::test]
+async fn fs_file_storage() {
+    let clock = ManualClock::default();
+    let (tmpdir, backend) = backend(FilesystemStorage::File, &clock).await;
+    let root = tmpdir.path();
+    backend.write_value("session-id", "key1", b"value1").await.unwrap();
+    backend.write_value("session-id", "key2", b"value2").await.unwrap();
+    assert!(root.join("session-id").is_file());
+    let other_backend = FilesystemBackend::with_storage(root, FilesystemStorage::File);
+    assert_eq!(
+        other_backend.read_value("session-id", "key2").await.unwrap().unwrap(),
+        b"value2"
+    );
+    assert_eq!(backend.get_sessions().await.unwrap(), vec![String::from("session-id")]);
+    assert!(backend.get_session_age("session-id").await.unwrap().is_some());
+    assert_eq!(
+        backend.read_value("session-id", "key1").await.unwrap().unwrap(),
+        b"value1"
+    );
+    backend.remove_value("session-id", "key1").await.unwrap();
+    assert!(backend.read_value("session-id", "key1").await.unwrap().is_none());
+    backend.remove_session("session-id").await.unwrap();
+    assert!(!root.join("session-id").exists());
+    assert!(backend.get_session_age("session-id").await.unwrap().is_none());
+
+    run(backend, clock).await;
+}
+
+#[tokio::test]
+async fn fs_concurrent_writes() {
+    let tmpdir = tempdir().expect("Failed to create temp directory");
+    let root = tmpdir.path();
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let backend = FilesystemBackend::builder(root).storage(storage).build().await.unwrap();
+        let session_id = format!("session-{storage:?}");
+        let mut tasks = Vec::new();
+        for idx in 0..16 {
+            let backend = backend.clone();
+            let session_id = session_id.clone();
+            tasks.push(tokio::spawn(async move {
+                backend
+                    .write_value(&session_id, &format!("key{idx}"), b"value")
+                    .await
+                    .unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let backend = backend.clone();
+        assert!(backend.get_session_age(&session_id).await.unwrap().is_some());
+        for idx in 0..16 {
+            assert!(
+                backend
+                    .read_value(&session_id, &format!("key{idx}"))
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+        backend.remove_session(&session_id).await.unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn fs_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = tempdir().expect("Failed to create temp directory");
+    let root = tmpdir.path().join("sessions");
+    let mode = |path: &std::path::Path| path.metadata().unwrap().permissions().mode() & 0o777;
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let backend = FilesystemBackend::builder(&root)
+            .storage(storage)
+            .create_root(true)
+            .file_mode(0o600)
+            .dir_mode(0o700)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(mode(&root), 0o700);
+        let session_id = format!("session-{storage:?}");
+        backend.write_value(&session_id, "key", b"value").await.unwrap();
+        let session_path = root.join(&session_id);
+        match storage {
+            FilesystemStorage::Directory => {
+                assert_eq!(mode(&session_path), 0o700);
+                assert_eq!(mode(&session_path.join("key")), 0o600);
+            }
+            FilesystemStorage::File => assert_eq!(mode(&session_path), 0o600),
+        }
+        backend.remove_session(&session_id).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn fs_marker_recovery() {
+    let tmpdir = tempdir().expect("Failed to create temp directory");
+    let root = tmpdir.path();
+    let build = |storage, marker_recovery| {
+        FilesystemBackend::builder(root)
+            .storage(storage)
+            .marker_recovery(marker_recovery)
+            .build()
+    };
+    let damage = |storage, session_id: &str| match storage {
+        FilesystemStorage::Directory => std::fs::write(root.join(session_id).join(".__created"), "damaged").unwrap(),
+        FilesystemStorage::File => std::fs::write(root.join(session_id), "damaged").unwrap(),
+    };
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let backend = build(storage, MarkerRecovery::Fail).await.unwrap();
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        damage(storage, "session-id");
+        assert!(backend.get_session_age("session-id").await.is_err());
+
+        let report = backend.fsck(MarkerRecovery::Fail).await.unwrap();
+        assert_eq!(report.checked(), 1);
+        assert_eq!(report.damaged().len(), 1);
+        assert_eq!(report.damaged()[0].session_id(), "session-id");
+        assert!(!report.damaged()[0].is_repaired());
+
+        let backend = build(storage, MarkerRecovery::UseMtime).await.unwrap();
+        assert!(backend.get_session_age("session-id").await.unwrap().is_some());
+        assert_eq!(backend.fsck(MarkerRecovery::Fail).await.unwrap().damaged().len(), 1);
+
+        let backend = build(storage, MarkerRecovery::Quarantine).await.unwrap();
+        assert!(backend.get_session_age("session-id").await.unwrap().is_none());
+        assert!(backend.get_sessions().await.unwrap().is_empty());
+        assert!(backend.fsck(MarkerRecovery::Fail).await.unwrap().damaged().is_empty());
+    }
+
+    let backend = build(FilesystemStorage::Directory, MarkerRecovery::Rewrite)
+        .await
+        .unwrap();
+    backend.write_value("session-id", "key", b"value").await.unwrap();
+    damage(FilesystemStorage::Directory, "session-id");
+    assert!(backend.get_session_age("session-id").await.unwrap().is_some());
+    assert!(backend.fsck(MarkerRecovery::Fail).await.unwrap().damaged().is_empty());
+    assert_eq!(
+        backend.read_value("session-id", "key").await.unwrap().unwrap(),
+        b"value"
+    );
+
+    let backend = build(FilesystemStorage::Directory, MarkerRecovery::Fail).await.unwrap();
+    damage(FilesystemStorage::Directory, "session-id");
+    let report = backend.fsck(MarkerRecovery::Rewrite).await.unwrap();
+    assert!(report.damaged()[0].is_repaired());
+    assert!(backend.get_session_age("session-id").await.unwrap().is_some());
+    backend.remove_session("session-id").await.unwrap();
+
+    let backend = build(FilesystemStorage::File, MarkerRecovery::Fail).await.unwrap();
+    std::fs::write(root.join("session-id"), "damaged\n3 5\nkeyvalue").unwrap();
+    let report = backend.fsck(MarkerRecovery::Rewrite).await.unwrap();
+    assert!(report.damaged()[0].is_repaired());
+    assert!(backend.fsck(MarkerRecovery::Fail).await.unwrap().damaged().is_empty());
+
+    // A header without a line break could hide values, so the file is not rewritten
+    std::fs::write(root.join("session-id"), "damaged").unwrap();
+    let report = backend.fsck(MarkerRecovery::Rewrite).await.unwrap();
+    assert!(!report.damaged()[0].is_repaired());
+    assert_eq!(std::fs::read(root.join("session-id")).unwrap(), b"damaged");
+    backend.remove_session("session-id").await.unwrap();
+
+    // Errors which are not caused by damaged data never quarantine a session
+    let backend = build(FilesystemStorage::Directory, MarkerRecovery::Quarantine)
+        .await
+        .unwrap();
+    std::fs::write(root.join("occupied-id"), "value").unwrap();
+    assert!(backend.get_session_age("occupied-id").await.is_err());
+    assert!(root.join("occupied-id").is_file());
+    std::fs::remove_file(root.join("occupied-id")).unwrap();
+    backend.write_value("session-id", "key", b"value").await.unwrap();
+    std::fs::remove_file(root.join("session-id").join(".__created")).unwrap();
+    std::fs::create_dir(root.join("session-id").join(".__created")).unwrap();
+    assert!(backend.get_session_age("session-id").await.is_err());
+    assert!(backend.fsck(MarkerRecovery::Quarantine).await.is_err());
+    assert_eq!(
+        backend.read_value("session-id", "key").await.unwrap().unwrap(),
+        b"value"
+    );
+}
+
+#[tokio::test]
+async fn fs_lock_files() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let tmpdir = tempdir().expect("Failed to create temp directory");
+        let root = tmpdir.path();
+        let backend = FilesystemBackend::builder(root).storage(storage).build().await.unwrap();
+        let locks_root = root.join(".__locks");
+        assert!(backend.get_session_age("unknown-id").await.unwrap().is_none());
+        assert!(backend.get_session_access("unknown-id").await.unwrap().is_none());
+        assert!(backend.read_value("unknown-id", "key").await.unwrap().is_none());
+        assert!(backend.read_all_values("unknown-id").await.unwrap().is_empty());
+        backend.touch_session("unknown-id").await.unwrap();
+        backend.remove_value("unknown-id", "key").await.unwrap();
+        backend.remove_session("unknown-id").await.unwrap();
+        assert!(backend.compare_and_swap("unknown-id", "key", None, None).await.unwrap());
+        assert!(!locks_root.join("unknown-id").exists());
+
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        std::fs::write(locks_root.join("removed-id"), "").unwrap();
+        let report = backend.fsck(MarkerRecovery::Fail).await.unwrap();
+        assert_eq!(report.removed_locks(), 1);
+        assert!(!locks_root.join("removed-id").exists());
+        assert!(locks_root.join("session-id").exists());
+    }
+}
+
+#[tokio::test]
+async fn fs_limits() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let tmpdir = tempdir().expect("Failed to create temp directory");
+        let backend = FilesystemBackend::builder(tmpdir.path())
+            .storage(storage)
+            .max_value_size(10)
+            .max_keys(2)
+            .max_total_bytes(100)
+            .build()
+            .await
+            .unwrap();
+        assert!(matches!(
+            backend.write_value("session-1", "key", &[0; 11]).await,
+            Err(FilesystemBackendError::ValueTooLarge(10))
+        ));
+        backend.write_value("session-1", "key1", &[0; 10]).await.unwrap();
+        backend.write_value("session-1", "key2", &[0; 10]).await.unwrap();
+        backend.write_value("session-1", "key2", &[0; 5]).await.unwrap();
+        assert!(matches!(
+            backend.write_value("session-1", "key3", &[0; 10]).await,
+            Err(FilesystemBackendError::TooManyKeys(2))
+        ));
+        let mut result = Ok(());
+        for idx in 0..20 {
+            result = backend.write_value(&format!("session-{idx}"), "key1", &[0; 10]).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(FilesystemBackendError::QuotaExceeded(100))));
+        for session_id in backend.get_sessions().await.unwrap() {
+            backend.remove_session(&session_id).await.unwrap();
+        }
+        backend.write_value("session-1", "key1", &[0; 10]).await.unwrap();
+    }
+
+    // Markers and locks are not counted, so usage does not drift after removal or refresh
+    let tmpdir = tempdir().expect("Failed to create temp directory");
+    let backend = FilesystemBackend::builder(tmpdir.path())
+        .max_total_bytes(30)
+        .build()
+        .await
+        .unwrap();
+    for idx in 0..3 {
+        backend
+            .write_value(&format!("session-{idx}"), "key", &[0; 10])
+            .await
+            .unwrap();
+    }
+    backend.refresh_usage().await.unwrap();
+    backend.remove_session("session-0").await.unwrap();
+    backend.write_value("session-3", "key", &[0; 10]).await.unwrap();
+    assert!(matches!(
+        backend.write_value("session-4", "key", &[0; 10]).await,
+        Err(FilesystemBackendError::QuotaExceeded(30))
+    ));
+}
+
+#[tokio::test]
+async fn fs_update() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        // Managers do not share session locks, just like separate processes
+        let managers = [SessionManager::new(backend.clone()), SessionManager::new(backend)];
+        let mut tasks = Vec::new();
+        for idx in 0..32 {
+            let session = managers[idx % 2].get_session("session-id");
+            tasks.push(tokio::spawn(async move {
+                session
+                    .update("counter", |value: Option<u64>| Some(value.unwrap_or(0) + 1))
+                    .await
+                    .unwrap()
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let session = managers[0].get_session("session-id");
+        assert_eq!(session.get::<_, u64>("counter").await.unwrap(), Some(32));
+        assert_eq!(session.update("counter", |_: Option<u64>| None).await.unwrap(), None);
+        assert!(session.get::<_, u64>("counter").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_increment() {
+    let (_tmpdir, backend) = backend(FilesystemStorage::Directory, &ManualClock::default()).await;
+    let managers = [SessionManager::new(backend.clone()), SessionManager::new(backend)];
+    let mut tasks = Vec::new();
+    for idx in 0..32 {
+        let session = managers[idx % 2].get_session("session-id");
+        tasks.push(tokio::spawn(
+            async move { session.increment("counter", 2).await.unwrap() },
+        ));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let session = managers[0].get_session("session-id");
+    assert_eq!(session.get::<_, i64>("counter").await.unwrap(), Some(64));
+    assert_eq!(session.increment("counter", -70).await.unwrap(), -6);
+
+    session.expire("counter", Duration::from_secs(100)).await.unwrap();
+    assert_eq!(session.increment("counter", 1).await.unwrap(), -5);
+    assert_eq!(session.get::<_, i64>("counter").await.unwrap(), Some(-5));
+
+    session.set("counter", &i64::MAX).await.unwrap();
+    assert!(matches!(
+        session.increment("counter", 1).await,
+        Err(SessionError::CounterOverflow)
+    ));
+    session.set("counter", &"value").await.unwrap();
+    assert!(session.increment("counter", 1).await.is_err());
+}
+
+#[tokio::test]
+async fn fs_buffered() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let manager = SessionManager::new(backend);
+        let session = manager.get_session("session-id");
+        session.set("key1", &"value1").await.unwrap();
+        session.set("key2", &"value2").await.unwrap();
+        session.expire("key2", Duration::from_secs(100)).await.unwrap();
+
+        let mut buffered = manager.get_session("session-id").into_buffered();
+        assert_eq!(
+            buffered.get::<_, String>("key1").await.unwrap().as_deref(),
+            Some("value1")
+        );
+        buffered.set("key1", &"new-value1").await.unwrap();
+        buffered.set("key2", &"new-value2").await.unwrap();
+        buffered.set("key3", &"value3").await.unwrap();
+        buffered.remove("key1").await.unwrap();
+        assert!(buffered.is_dirty());
+        assert!(buffered.get::<_, String>("key1").await.unwrap().is_none());
+        assert_eq!(
+            session.get::<_, String>("key1").await.unwrap().as_deref(),
+            Some("value1")
+        );
+        assert!(session.get::<_, String>("key3").await.unwrap().is_none());
+
+        buffered.commit().await.unwrap();
+        assert!(!buffered.is_dirty());
+        assert!(session.get::<_, String>("key1").await.unwrap().is_none());
+        assert_eq!(
+            session.get::<_, String>("key2").await.unwrap().as_deref(),
+            Some("new-value2")
+        );
+        assert_eq!(
+            session.get::<_, String>("key3").await.unwrap().as_deref(),
+            Some("value3")
+        );
+        session.remove("key2").await.unwrap();
+        buffered.rollback();
+        assert!(buffered.get::<_, String>("key2").await.unwrap().is_none());
+
+        let mut buffered = manager.get_session("session-id").into_buffered().commit_on_drop(true);
+        buffered.set("key4", &"value4").await.unwrap();
+        drop(buffered);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            session.get::<_, String>("key4").await.unwrap().as_deref(),
+            Some("value4")
+        );
+
+        let mut buffered = manager.get_session("session-id").into_buffered();
+        buffered.set("key5", &"value5").await.unwrap();
+        drop(buffered);
+        assert!(session.get::<_, String>("key5").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_batch() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let clock = ManualClock::default();
+        let (_tmpdir, backend) = backend(storage, &clock).await;
+        backend
+            .write_values("session-id", &[("key1", b"value1"), ("key2", b"value2")])
+            .await
+            .unwrap();
+        assert_eq!(
+            backend
+                .read_values("session-id", &["key2", "missing", "key1"])
+                .await
+                .unwrap(),
+            [Some(b"value2".to_vec()), None, Some(b"value1".to_vec())]
+        );
+        backend.remove_values("session-id", &["key1", "missing"]).await.unwrap();
+        assert_eq!(
+            backend.read_values("session-id", &["key1", "key2"]).await.unwrap(),
+            [None, Some(b"value2".to_vec())]
+        );
+
+        let manager = SessionManager::new(backend);
+        let session = manager.get_session("other-session-id");
+        session.set("key1", &1).await.unwrap();
+        session.expire("key1", Duration::from_secs(100)).await.unwrap();
+        session.set_many(&[("key1", 2), ("key3", 3)]).await.unwrap();
+        assert_eq!(
+            session.get_many::<_, u64>(&["key1", "key2", "key3"]).await.unwrap(),
+            [Some(2), None, Some(3)]
+        );
+        session.expire("key1", Duration::ZERO).await.unwrap();
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(
+            session.get_many::<_, u64>(&["key1", "key3"]).await.unwrap(),
+            [None, Some(3)]
+        );
+    }
+}
+
+#[tokio::test]
+async fn fs_session_lifetime() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let clock = ManualClock::default();
+        let (_tmpdir, backend) = backend(storage, &clock).await;
+        // Sub-second part is rounded up to a whole second
+        let idle_manager = SessionManager::builder(backend.clone())
+            .idle_timeout(Duration::from_millis(2500))
+            .clock(clock.clone())
+            .build();
+        let aged_manager = SessionManager::builder(backend.clone())
+            .max_age(Duration::from_secs(3))
+            .clock(clock.clone())
+            .build();
+        let idle_session = idle_manager.get_session("idle-session-id");
+        let aged_session = aged_manager.get_session("aged-session-id");
+        idle_session.set("key", &"value").await.unwrap();
+        aged_session.set("key", &"value").await.unwrap();
+
+        clock.advance(Duration::from_secs(1));
+        assert!(idle_session.get::<_, String>("key").await.unwrap().is_some());
+        assert!(aged_session.get::<_, String>("key").await.unwrap().is_some());
+        clock.advance(Duration::from_secs(1));
+        idle_session.touch().await.unwrap();
+        let (created, accessed) = backend.get_session_times("idle-session-id").await.unwrap().unwrap();
+        assert_eq!(accessed - created, 2);
+        clock.advance(Duration::from_secs(1));
+        assert!(idle_session.get::<_, String>("key").await.unwrap().is_some());
+        assert!(aged_session.get::<_, String>("key").await.unwrap().is_none());
+        assert!(backend.get_session_age("aged-session-id").await.unwrap().is_none());
+
+        clock.advance(Duration::from_secs(3));
+        assert!(idle_session.get::<_, String>("key").await.unwrap().is_none());
+        assert!(backend.get_session_age("idle-session-id").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_access_resolution() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let tmpdir = tempdir().expect("Failed to create temp directory");
+        let clock = ManualClock::default();
+        let backend = FilesystemBackend::builder(tmpdir.path())
+            .storage(storage)
+            .clock(clock.clone())
+            // Rounded up to 10 seconds
+            .access_resolution(Duration::from_millis(9500))
+            .build()
+            .await
+            .unwrap();
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        let created = backend.get_session_age("session-id").await.unwrap().unwrap();
+
+        clock.advance(Duration::from_secs(9));
+        backend.read_value("session-id", "key").await.unwrap();
+        assert_eq!(backend.get_session_access("session-id").await.unwrap(), Some(created));
+
+        clock.advance(Duration::from_secs(1));
+        backend.read_value("session-id", "key").await.unwrap();
+        assert_eq!(
+            backend.get_session_access("session-id").await.unwrap(),
+            Some(created + 10)
+        );
+    }
+}
+
+async fn run_idle_collector(storage: FilesystemStorage) {
+    let clock = ManualClock::default();
+    let (_tmpdir, backend) = backend(storage, &clock).await;
+    let manager = SessionManager::new(backend.clone());
+    let session = manager.get_session("session-id");
+    session.set("key", &"value").await.unwrap();
+    let created = backend.get_session_age("session-id").await.unwrap().unwrap();
+
+    let mut collector = SessionCollector::new(backend.clone(), Duration::from_millis(10), Duration::from_secs(3))
+        .mode(CollectorMode::Idle);
+    for _ in 0..4 {
+        clock.advance(Duration::from_secs(1));
+        collector.collect_once().await;
+        assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    }
+    let accessed = backend.get_session_access("session-id").await.unwrap().unwrap();
+    assert!(accessed > created);
+    assert_eq!(backend.get_session_age("session-id").await.unwrap(), Some(created));
+    clock.advance(Duration::from_secs(3));
+    collector.collect_once().await;
+    assert!(backend.get_session_access("session-id").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn fs_idle_collector() {
+    tokio::join!(
+        run_idle_collector(FilesystemStorage::Directory),
+        run_idle_collector(FilesystemStorage::File)
+    );
+}
+
+#[tokio::test]
+async fn fs_create_session() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let manager = SessionManager::builder(backend.clone()).id_length(20).build();
+        let session = manager.create_session().await.unwrap();
+        let session_id = backend.get_sessions().await.unwrap().pop().unwrap();
+        assert_eq!(session_id.len(), 20);
+        assert!(!backend.create_session(&session_id).await.unwrap());
+        session.set("key", &"value").await.unwrap();
+
+        let session = manager.load_session(session_id.as_str()).await.unwrap().unwrap();
+        assert_eq!(session.get::<_, String>("key").await.unwrap().as_deref(), Some("value"));
+        assert!(manager.load_session("unknown-session-id").await.unwrap().is_none());
+        assert!(manager.load_session("../../../../etc/passwd").await.unwrap().is_none());
+        assert!(manager.load_session(&session_id[1..]).await.unwrap().is_none());
+        for invalid_id in ["", "..", "../session", "a/b", "a\\b", ".__locks"] {
+            assert!(matches!(
+                backend.write_value(invalid_id, "key", b"value").await,
+                Err(FilesystemBackendError::InvalidSessionId(_))
+            ));
+            assert!(matches!(
+                backend.read_value(invalid_id, "key").await,
+                Err(FilesystemBackendError::InvalidSessionId(_))
+            ));
+        }
+        backend.remove_session(&session_id).await.unwrap();
+        assert!(manager.load_session(session_id).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_invalid_keys() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        backend.write_value("session-id", "key", b"value").await.unwrap();
+        let created = backend.get_session_age("session-id").await.unwrap();
+        let invalid_keys = [
+            ".__created",
+            ".__accessed",
+            ".__key",
+            "",
+            ".",
+            "..",
+            "../escaped",
+            "a/b",
+            "a\\b",
+            "a\0b",
+        ];
+        for key in invalid_keys {
+            assert!(matches!(
+                backend.write_value("session-id", key, b"1").await,
+                Err(FilesystemBackendError::InvalidKey(_))
+            ));
+            assert!(matches!(
+                backend.read_value("session-id", key).await,
+                Err(FilesystemBackendError::InvalidKey(_))
+            ));
+            assert!(matches!(
+                backend
+                    .write_values("session-id", &[("other", b"1"), (key, b"1")])
+                    .await,
+                Err(FilesystemBackendError::InvalidKey(_))
+            ));
+            assert!(matches!(
+                backend.remove_value("session-id", key).await,
+                Err(FilesystemBackendError::InvalidKey(_))
+            ));
+        }
+        assert!(!tmpdir.path().join("escaped").exists());
+        assert_eq!(backend.get_session_age("session-id").await.unwrap(), created);
+        assert_eq!(
+            backend.read_all_values("session-id").await.unwrap(),
+            [(String::from("key"), b"value".to_vec())]
+        );
+    }
+}
+
+#[tokio::test]
+async fn fs_regenerate_id() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let manager = SessionManager::new(backend.clone());
+        let mut session = manager.create_session().await.unwrap();
+        session.set("key", &"value").await.unwrap();
+        let old_id = String::from(session.id());
+        let created = backend.get_session_age(&old_id).await.unwrap();
+
+        session.regenerate_id().await.unwrap();
+        assert_ne!(session.id(), old_id);
+        assert_eq!(backend.get_sessions().await.unwrap(), vec![String::from(session.id())]);
+        assert_eq!(backend.get_session_age(session.id()).await.unwrap(), created);
+        assert_eq!(session.get::<_, String>("key").await.unwrap().as_deref(), Some("value"));
+        assert!(manager.load_session(old_id.as_str()).await.unwrap().is_none());
+
+        backend.create_session("taken-session-id").await.unwrap();
+        assert!(!backend.rename_session(session.id(), "taken-session-id").await.unwrap());
+        assert!(!backend.rename_session(session.id(), session.id()).await.unwrap());
+        assert!(!backend.rename_session("unknown-session-id", &old_id).await.unwrap());
+        assert!(backend.get_session_age(&old_id).await.unwrap().is_none());
+
+        let mut session = manager.get_session("unknown-session-id");
+        assert!(matches!(
+            session.regenerate_id().await,
+            Err(SessionError::SessionNotFound)
+        ));
+        assert_eq!(session.id(), "unknown-session-id");
+        assert_eq!(backend.get_sessions().await.unwrap().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn fs_session_metadata() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let clock = ManualClock::default();
+        let (_tmpdir, backend) = backend(storage, &clock).await;
+        let manager = SessionManager::new(backend);
+        let session = manager.get_session("session-id");
+        assert!(!session.exists().await.unwrap());
+        assert!(session.created_at().await.unwrap().is_none());
+        assert!(session.ttl("key").await.unwrap().is_none());
+
+        let before = clock.now() - Duration::from_secs(1);
+        session.set("key", &"value").await.unwrap();
+        assert!(session.exists().await.unwrap());
+        let created_at = session.created_at().await.unwrap().unwrap();
+        assert!(created_at >= before && created_at <= clock.now());
+        assert!(session.ttl("key").await.unwrap().is_none());
+        session.expire("key", Duration::from_secs(60)).await.unwrap();
+        let ttl = session.ttl("key").await.unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(58) && ttl <= Duration::from_secs(60));
+        assert!(session.ttl("unknown-key").await.unwrap().is_none());
+        session
+            .expire_at("key", clock.now() + Duration::from_millis(1500))
+            .await
+            .unwrap();
+        let ttl = session.ttl("key").await.unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(1) && ttl <= Duration::from_millis(1500));
+        session
+            .expire_at("key", clock.now() - Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(session.get::<_, String>("key").await.unwrap().is_none());
+        assert!(session.ttl("key").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_value_ttl() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let clock = ManualClock::default();
+        let (_tmpdir, backend) = backend(storage, &clock).await;
+        let manager = SessionManager::builder(backend).clock(clock.clone()).build();
+        let session = manager.get_session("session-id");
+        session
+            .set_with_ttl("key", &"value", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(session.ttl("key").await.unwrap(), Some(Duration::from_secs(10)));
+        session.set("key", &"new-value").await.unwrap();
+        assert_eq!(session.ttl("key").await.unwrap(), Some(Duration::from_secs(10)));
+        session.persist("key").await.unwrap();
+        assert!(session.ttl("key").await.unwrap().is_none());
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(session.get::<_, String>("key").await.unwrap().unwrap(), "new-value");
+        session.persist("unknown-key").await.unwrap();
+        assert!(session.get::<_, String>("unknown-key").await.unwrap().is_none());
+
+        session
+            .set_with_ttl("expired", &"value", Duration::from_secs(1))
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+        session.persist("expired").await.unwrap();
+        assert!(session.get::<_, String>("expired").await.unwrap().is_none());
+
+        let mut buffered = session.clone().into_buffered();
+        buffered
+            .set_with_ttl("buffered", &"value", Duration::from_secs(10))
+            .await
+            .unwrap();
+        buffered.commit().await.unwrap();
+        assert_eq!(session.ttl("buffered").await.unwrap(), Some(Duration::from_secs(10)));
+        buffered.persist("buffered").await.unwrap();
+        buffered.commit().await.unwrap();
+        assert!(session.ttl("buffered").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn fs_expired_values() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let clock = ManualClock::default();
+        let (_tmpdir, backend) = backend(storage, &clock).await;
+        let manager = SessionManager::builder(backend.clone()).clock(clock.clone()).build();
+        let session = manager.get_session("session-id");
+        session
+            .set_with_ttl("short", &"value", Duration::from_secs(1))
+            .await
+            .unwrap();
+        session.set("kept", &"value").await.unwrap();
+        session
+            .set_with_ttl("long", &"value", Duration::from_secs(60))
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert!(backend.read_value("session-id", "short").await.unwrap().is_some());
+        assert!(session.get::<_, String>("short").await.unwrap().is_none());
+        assert!(backend.read_value("session-id", "short").await.unwrap().is_none());
+
+        session
+            .set_with_ttl("short", &"value", Duration::from_secs(1))
+            .await
+            .unwrap();
+        let accessed = backend.get_session_access("session-id").await.unwrap();
+        clock.advance(Duration::from_secs(2));
+        let mut collector = SessionCollector::new(backend.clone(), Duration::from_millis(10), Duration::from_secs(60))
+            .mode(CollectorMode::Idle)
+            .remove_expired_values(true);
+        collector.collect_once().await;
+        assert_eq!(backend.get_session_access("session-id").await.unwrap(), accessed);
+        let mut keys = Vec::from_iter(
+            backend
+                .read_all_values("session-id")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key),
+        );
+        keys.sort();
+        assert_eq!(keys, ["kept", "long"]);
+    }
+}
+
+#[tokio::test]
+async fn fs_typed_keys() {
+    const USER_ID: SessionKey<u64> = SessionKey::new("user_id");
+    const TOKEN: SessionKey<String> = SessionKey::new("token").ttl(Duration::from_secs(10));
+
+    let clock = ManualClock::default();
+    let (_tmpdir, backend) = backend(FilesystemStorage::Directory, &clock).await;
+    let manager = SessionManager::builder(backend).clock(clock.clone()).build();
+    let session = manager.get_session("session-id");
+    assert!(session.get_typed(&USER_ID).await.unwrap().is_none());
+    session.set_typed(&USER_ID, &42).await.unwrap();
+    assert_eq!(session.get_typed(&USER_ID).await.unwrap(), Some(42));
+    assert!(session.ttl(USER_ID).await.unwrap().is_none());
+    session.set_typed(&TOKEN, &String::from("secret")).await.unwrap();
+    assert_eq!(session.ttl(TOKEN).await.unwrap(), Some(Duration::from_secs(10)));
+    assert_eq!(
+        session.get::<_, String>("token").await.unwrap().as_deref(),
+        Some("secret")
+    );
+
+    let mut buffered = session.clone().into_buffered();
+    buffered.set_typed(&USER_ID, &43).await.unwrap();
+    assert_eq!(buffered.get_typed(&USER_ID).await.unwrap(), Some(43));
+    buffered.commit().await.unwrap();
+    assert_eq!(session.get_typed(&USER_ID).await.unwrap(), Some(43));
+    clock.advance(Duration::from_secs(20));
+    assert!(session.get_typed(&TOKEN).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn fs_flash() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let manager = SessionManager::new(backend.clone());
+        let session = manager.get_session("session-id");
+        assert!(session.take_flashes().await.unwrap().is_empty());
+        session.flash("success", "Saved").await.unwrap();
+        session.flash("error", String::from("Failed")).await.unwrap();
+        assert_eq!(
+            session.take_flashes().await.unwrap(),
+            [Flash::new("success", "Saved"), Flash::new("error", "Failed")]
+        );
+        assert!(session.take_flashes().await.unwrap().is_empty());
+        assert!(
+            backend
+                .read_value("session-id", "__seance_flash")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
+
+#[tokio::test]
+async fn fs_namespace() {
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let manager = SessionManager::new(backend.clone());
+        let session = manager.get_session("session-id");
+        let cart = session.namespace("cart").unwrap();
+        let auth = session.namespace("auth").unwrap();
+        cart.set("id", &1).await.unwrap();
+        auth.set("id", &2).await.unwrap();
+        session.set("id", &3).await.unwrap();
+        session.set("cart:id", &4).await.unwrap();
+        session.set("cart.id", &5).await.unwrap();
+        assert_eq!(cart.get::<_, u32>("id").await.unwrap(), Some(1));
+        assert_eq!(auth.get::<_, u32>("id").await.unwrap(), Some(2));
+        assert_eq!(session.get::<_, u32>("__seance_ns.cart.id").await.unwrap(), Some(1));
+        for name in ["", "cart.items", "cart:items", "a/b", "a b"] {
+            assert!(matches!(
+                session.namespace(name),
+                Err(SessionError::InvalidNamespace(_))
+            ));
+            assert!(matches!(cart.namespace(name), Err(SessionError::InvalidNamespace(_))));
+        }
+        assert_eq!(cart.increment("count", 2).await.unwrap(), 2);
+
+        let items = cart.namespace("items").unwrap();
+        items.set("apple", &5).await.unwrap();
+        assert_eq!(
+            session.get::<_, u32>("__seance_ns.cart.items.apple").await.unwrap(),
+            Some(5)
+        );
+        items.clear().await.unwrap();
+        assert!(items.get::<_, u32>("apple").await.unwrap().is_none());
+        assert_eq!(cart.get::<_, u32>("id").await.unwrap(), Some(1));
+
+        // A key of a parent namespace never collides with a key of a nested one
+        assert!(matches!(
+            cart.set("items.apple", &1).await,
+            Err(SessionError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            cart.get::<_, u32>("items.apple").await,
+            Err(SessionError::InvalidKey(_))
+        ));
+        cart.set("items-apple", &6).await.unwrap();
+        items.set("apple", &5).await.unwrap();
+        items.clear().await.unwrap();
+        assert_eq!(cart.get::<_, u32>("items-apple").await.unwrap(), Some(6));
+        cart.remove("items-apple").await.unwrap();
+
+        items.set("apple", &5).await.unwrap();
+        cart.clear().await.unwrap();
+        assert!(cart.get::<_, u32>("id").await.unwrap().is_none());
+        assert!(cart.get::<_, i64>("count").await.unwrap().is_none());
+        assert!(items.get::<_, u32>("apple").await.unwrap().is_none());
+        assert_eq!(auth.get::<_, u32>("id").await.unwrap(), Some(2));
+        assert_eq!(session.get::<_, u32>("id").await.unwrap(), Some(3));
+        assert_eq!(session.get::<_, u32>("cart:id").await.unwrap(), Some(4));
+        assert_eq!(session.get::<_, u32>("cart.id").await.unwrap(), Some(5));
+    }
+}
+
+#[cfg(feature = "derive")]
+#[tokio::test]
+async fn fs_derive() {
+    use seance::SessionData;
+
+    fn default_theme() -> String {
+        String::from("light")
+    }
+
+    #[allow(dead_code)]
+    #[derive(SessionData)]
+    struct UserSession {
+        user_id: u64,
+        #[session(key = "csrf", ttl = 10)]
+        token: String,
+        #[session(default)]
+        visits: u32,
+        #[session(default = "default_theme")]
+        theme: String,
+        r#type: Option<String>,
+    }
+
+    let clock = ManualClock::default();
+    let (_tmpdir, backend) = backend(FilesystemStorage::Directory, &clock).await;
+    let manager = SessionManager::builder(backend).clock(clock.clone()).build();
+    let session = manager.get_session("session-id");
+    assert!(session.user_id().await.unwrap().is_none());
+    assert_eq!(session.visits().await.unwrap(), 0);
+    assert_eq!(session.theme().await.unwrap(), "light");
+
+    session.set_user_id(&42).await.unwrap();
+    assert_eq!(session.user_id().await.unwrap(), Some(42));
+    assert_eq!(session.get::<_, u64>("user_id").await.unwrap(), Some(42));
+    session.remove_user_id().await.unwrap();
+    assert!(session.user_id().await.unwrap().is_none());
+
+    session.set_token(&String::from("secret")).await.unwrap();
+    assert_eq!(UserSession::TOKEN.name(), "csrf");
+    assert_eq!(
+        session.get::<_, String>("csrf").await.unwrap().as_deref(),
+        Some("secret")
+    );
+    assert_eq!(session.ttl("csrf").await.unwrap(), Some(Duration::from_secs(10)));
+    clock.advance(Duration::from_secs(20));
+    assert!(session.token().await.unwrap().is_none());
+
+    session.set_visits(&3).await.unwrap();
+    assert_eq!(session.visits().await.unwrap(), 3);
+
+    session.set_type(&Some(String::from("admin"))).await.unwrap();
+    assert_eq!(UserSession::TYPE.name(), "type");
+    assert_eq!(session.r#type().await.unwrap(), Some(Some(String::from("admin"))));
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn fs_codec() {
+    use seance::codec::MessagePackCodec;
+
+    for storage in [FilesystemStorage::Directory, FilesystemStorage::File] {
+        let (_tmpdir, backend) = backend(storage, &ManualClock::default()).await;
+        let json_session = SessionManager::new(backend.clone()).get_session("session-id");
+        json_session.set("json", &"value").await.unwrap();
+        json_session.increment("counter", 2).await.unwrap();
+
+        let manager = SessionManager::builder(backend.clone()).codec(MessagePackCodec).build();
+        let session = manager.get_session("session-id");
+        session.set("msgpack", &vec![1u8, 2, 3]).await.unwrap();
+        let data = backend.read_value("session-id", "msgpack").await.unwrap().unwrap();
+        assert_eq!(data[0], 0x01);
+        session.expire("msgpack", Duration::from_secs(60)).await.unwrap();
+        assert!(session.ttl("msgpack").await.unwrap().is_some());
+        assert_eq!(session.get::<_, Vec<u8>>("msgpack").await.unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(
+            session.get::<_, String>("json").await.unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(session.increment("counter", 1).await.unwrap(), 3);
+        assert_eq!(
+            json_session.get::<_, Vec<u8>>("msgpack").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+
+        let mut buffered = session.into_buffered();
+        buffered.set("buffered", &"value").await.unwrap();
+        buffered.commit().await.unwrap();
+        assert_eq!(
+            json_session.get::<_, String>("buffered").await.unwrap().as_deref(),
+            Some("value")
+        );
+    }
+}
+
+#[tokio::test]
+async fn fs_migration() {
+    #[derive(Deserialize, Serialize)]
+    struct UserV1 {
+        name: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct UserV2 {
+        first_name: String,
+        last_name: String,
+    }
+
+    let (_tmpdir, backend) = backend(FilesystemStorage::Directory, &ManualClock::default()).await;
+    let session = SessionManager::new(backend.clone()).get_session("session-id");
+    session.set("user", &"Jane Doe").await.unwrap();
+    session.expire("user", Duration::from_secs(60)).await.unwrap();
+
+    let manager = SessionManager::builder(backend.clone())
+        .migration("user", |name: String| UserV1 { name })
+        .migration("user", |user: UserV1| {
+            let (first_name, last_name) = user.name.split_once(' ').unwrap_or((&user.name, ""));
+            UserV2 {
+                first_name: String::from(first_name),
+                last_name: String::from(last_name),
+            }
+        })
+        .build();
+    let session = manager.get_session("session-id");
+    let user = UserV2 {
+        first_name: String::from("Jane"),
+        last_name: String::from("Doe"),
+    };
+    assert_eq!(session.get::<_, UserV2>("user").await.unwrap().as_ref(), Some(&user));
+    assert!(session.ttl("user").await.unwrap().is_some());
+
+    let updated = session
+        .update("user", |user: Option<UserV2>| {
+            user.map(|user| UserV2 {
+                last_name: String::from("Roe"),
+                ..user
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(updated.last_name, "Roe");
+    let data = backend.read_value("session-id", "user").await.unwrap().unwrap();
+    assert!(String::from_utf8(data).unwrap().contains(r#""version":2"#));
+    assert!(session.ttl("user").await.unwrap().is_some());
+    assert_eq!(session.get::<_, UserV2>("user").await.unwrap(), Some(updated));
+
+    session.set("user", &"John Smith").await.unwrap();
+    assert!(session.get::<_, UserV2>("user").await.is_err());
+
+    // Migrations do not depend on a codec, counters of migrated keys are stored with a version
+    session.increment("counter", 2).await.unwrap();
+    let manager = SessionManager::builder(backend.clone())
+        .migration("counter", |counter: i64| counter * 10)
+        .codec(JsonCodec)
+        .build();
+    let session = manager.get_session("session-id");
+    assert_eq!(session.increment("counter", 1).await.unwrap(), 21);
+    assert_eq!(session.increment("counter", 1).await.unwrap(), 22);
+    let data = backend.read_value("session-id", "counter").await.unwrap().unwrap();
+    assert!(String::from_utf8(data).unwrap().contains(r#""version":1"#));
+    assert_eq!(session.get::<_, i64>("counter").await.unwrap(), Some(22));
 }
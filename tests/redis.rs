@@ -4,9 +4,11 @@ use std::{
 };
 
 use redis::Client;
-use tokio::time::sleep;
 
-use seance::{SessionCollector, SessionManager, backend::redis::RedisBackend};
+use seance::{
+    Flash, ManualClock, SessionCollector, SessionError, SessionManager,
+    backend::{SessionBackend, redis::RedisBackend},
+};
 
 const DEFAULT_ADDRESS: &str = "redis://127.0.0.1:6379";
 
@@ -20,27 +22,235 @@ async fn redis() {
     println!("REDIS ADDRESS: {address:?}");
     let client = Client::open(address).unwrap();
     let manager = client.get_multiplexed_tokio_connection().await.unwrap();
-    let backend = RedisBackend::new("test-seance", manager);
-    let manager = SessionManager::new(backend.clone());
-    let mut session = manager.get_session("session-id");
+    let clock = ManualClock::default();
+    let backend = RedisBackend::new("test-seance", manager).clock(clock.clone());
+    let manager = SessionManager::builder(backend.clone()).clock(clock.clone()).build();
+    let session = manager.get_session("session-id");
     session.set("key", &"value").await.unwrap();
     assert_eq!("value", session.get::<_, String>("key").await.unwrap().unwrap());
     session.remove("key").await.unwrap();
     assert!(session.get::<_, String>("key").await.unwrap().is_none());
     session.set("key", &"value").await.unwrap();
-    session.expire("key", 1).await.unwrap();
-    sleep(Duration::from_secs(2)).await;
+    session.expire("key", Duration::from_millis(300)).await.unwrap();
+    clock.advance(Duration::from_millis(100));
+    assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    clock.advance(Duration::from_millis(300));
     assert!(session.get::<_, String>("key").await.unwrap().is_none());
 
-    let gc_period = Duration::from_secs(1);
-    let session_lifetime = Duration::from_secs(1);
-    let mut collector = SessionCollector::new(backend, gc_period, session_lifetime);
-    let handle = collector.get_handle();
+    let gc_period = Duration::from_millis(10);
+    let session_lifetime = Duration::from_secs(3);
+    let mut collector = SessionCollector::new(backend.clone(), gc_period, session_lifetime);
     session.set("key", &"value").await.unwrap();
-    tokio::spawn(async move {
+    collector.collect_once().await;
+    assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    clock.advance(Duration::from_secs(5));
+    collector.collect_once().await;
+    assert!(session.get::<_, String>("key").await.unwrap().is_none());
+
+    let handle = collector.get_handle();
+    let task = tokio::spawn(async move {
         collector.run().await;
     });
-    sleep(Duration::from_secs(2)).await;
     handle.shutdown().await;
-    assert!(session.get::<_, String>("key").await.unwrap().is_none());
+    task.await.unwrap();
+
+    let mut tasks = Vec::new();
+    for _ in 0..32 {
+        // Separate managers do not share session locks, just like separate processes
+        let session = SessionManager::new(backend.clone()).get_session("session-id");
+        tasks.push(tokio::spawn(async move {
+            session
+                .update("counter", |value: Option<u64>| Some(value.unwrap_or(0) + 1))
+                .await
+                .unwrap()
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(session.get::<_, u64>("counter").await.unwrap(), Some(32));
+    assert_eq!(session.update("counter", |_: Option<u64>| None).await.unwrap(), None);
+    assert!(session.get::<_, u64>("counter").await.unwrap().is_none());
+
+    assert_eq!(session.increment("counter", 2).await.unwrap(), 2);
+    assert_eq!(session.increment("counter", -5).await.unwrap(), -3);
+    assert_eq!(session.get::<_, i64>("counter").await.unwrap(), Some(-3));
+    session.expire("counter", Duration::from_secs(100)).await.unwrap();
+    assert_eq!(session.increment("counter", 1).await.unwrap(), -2);
+    session.increment("counter", i64::MAX).await.unwrap();
+    session.persist("counter").await.unwrap();
+    assert!(matches!(
+        session.increment("counter", i64::MAX).await,
+        Err(SessionError::CounterOverflow)
+    ));
+    session.remove("counter").await.unwrap();
+
+    let mut buffered = manager.get_session("session-id").into_buffered();
+    buffered.set("key1", &"value1").await.unwrap();
+    buffered.set("key2", &"value2").await.unwrap();
+    assert!(session.get::<_, String>("key1").await.unwrap().is_none());
+    buffered.commit().await.unwrap();
+    assert_eq!(
+        session.get::<_, String>("key1").await.unwrap().as_deref(),
+        Some("value1")
+    );
+    buffered.remove("key1").await.unwrap();
+    buffered.commit().await.unwrap();
+    assert!(session.get::<_, String>("key1").await.unwrap().is_none());
+    let mut buffered = manager.get_session("session-id").into_buffered();
+    assert_eq!(
+        buffered.get::<_, String>("key2").await.unwrap().as_deref(),
+        Some("value2")
+    );
+    buffered.remove("key2").await.unwrap();
+    buffered.commit().await.unwrap();
+
+    session.set_many(&[("key1", 1), ("key2", 2)]).await.unwrap();
+    assert_eq!(
+        session.get_many::<_, u64>(&["key2", "missing", "key1"]).await.unwrap(),
+        [Some(2), None, Some(1)]
+    );
+    backend.remove_values("session-id", &["key1", "key2"]).await.unwrap();
+    assert_eq!(
+        session.get_many::<_, u64>(&["key1", "key2"]).await.unwrap(),
+        [None, None]
+    );
+
+    let idle_manager = SessionManager::builder(backend.clone())
+        .idle_timeout(Duration::from_secs(3))
+        .clock(clock.clone())
+        .build();
+    let idle_session = idle_manager.get_session("idle-session-id");
+    idle_session.set("key", &"value").await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    idle_session.touch().await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert!(idle_session.get::<_, String>("key").await.unwrap().is_some());
+    clock.advance(Duration::from_secs(3));
+    assert!(idle_session.get::<_, String>("key").await.unwrap().is_none());
+    assert!(backend.get_session_access("idle-session-id").await.unwrap().is_none());
+
+    let session = manager.get_session("access-session-id");
+    session.set("key", &"value").await.unwrap();
+    let created = backend.get_session_age("access-session-id").await.unwrap().unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert!(session.get::<_, String>("key").await.unwrap().is_some());
+    assert!(backend.get_session_access("access-session-id").await.unwrap().unwrap() > created);
+    backend.remove_session("access-session-id").await.unwrap();
+
+    let session = manager.create_session().await.unwrap();
+    session.set("key", &"value").await.unwrap();
+    let session_ids = backend.get_sessions().await.unwrap();
+    for session_id in session_ids {
+        if session_id.len() == 32 {
+            assert!(!backend.create_session(&session_id).await.unwrap());
+            let session = manager.load_session(session_id.as_str()).await.unwrap().unwrap();
+            assert_eq!(session.get::<_, String>("key").await.unwrap().as_deref(), Some("value"));
+            backend.remove_session(&session_id).await.unwrap();
+            assert!(manager.load_session(session_id).await.unwrap().is_none());
+        }
+    }
+    assert!(manager.load_session("unknown-session-id").await.unwrap().is_none());
+
+    // Writes never reset creation time, even when a session has no values
+    assert!(backend.create_session("created-session-id").await.unwrap());
+    let created = backend.get_session_age("created-session-id").await.unwrap();
+    let session = manager.get_session("created-session-id");
+    clock.advance(Duration::from_secs(2));
+    session.set("key", &"value").await.unwrap();
+    assert_eq!(backend.get_session_age("created-session-id").await.unwrap(), created);
+    session.remove("key").await.unwrap();
+    clock.advance(Duration::from_secs(2));
+    session.update("key", |_: Option<u64>| Some(1)).await.unwrap();
+    session.remove("key").await.unwrap();
+    session.increment("counter", 1).await.unwrap();
+    assert_eq!(backend.get_session_age("created-session-id").await.unwrap(), created);
+    backend.remove_session("created-session-id").await.unwrap();
+
+    let mut session = manager.create_session().await.unwrap();
+    session.set("key", &"value").await.unwrap();
+    let old_id = String::from(session.id());
+    let created = backend.get_session_age(&old_id).await.unwrap();
+    session.regenerate_id().await.unwrap();
+    assert_ne!(session.id(), old_id);
+    assert_eq!(backend.get_session_age(session.id()).await.unwrap(), created);
+    assert_eq!(session.get::<_, String>("key").await.unwrap().as_deref(), Some("value"));
+    assert!(manager.load_session(old_id.as_str()).await.unwrap().is_none());
+    assert!(!backend.rename_session(session.id(), session.id()).await.unwrap());
+    backend.remove_session(session.id()).await.unwrap();
+    assert!(!backend.rename_session(session.id(), &old_id).await.unwrap());
+    assert!(backend.get_session_age(&old_id).await.unwrap().is_none());
+    assert!(matches!(
+        session.regenerate_id().await,
+        Err(SessionError::SessionNotFound)
+    ));
+
+    let session = manager.get_session("metadata-session-id");
+    assert!(!session.exists().await.unwrap());
+    assert!(session.created_at().await.unwrap().is_none());
+    session.set("key", &"value").await.unwrap();
+    assert!(session.exists().await.unwrap());
+    assert!(session.created_at().await.unwrap().is_some());
+    assert!(session.ttl("key").await.unwrap().is_none());
+    session.expire("key", Duration::from_secs(60)).await.unwrap();
+    assert!(session.ttl("key").await.unwrap().unwrap() > Duration::from_secs(58));
+    backend.remove_session("metadata-session-id").await.unwrap();
+
+    let session = manager.get_session("ttl-session-id");
+    session
+        .set_with_ttl("key", &"value", Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(session.ttl("key").await.unwrap(), Some(Duration::from_secs(10)));
+    session.persist("key").await.unwrap();
+    assert!(session.ttl("key").await.unwrap().is_none());
+    session
+        .set_with_ttl("expired", &"value", Duration::from_secs(1))
+        .await
+        .unwrap();
+    session
+        .set_with_ttl("collected", &"value", Duration::from_secs(1))
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(2));
+    assert!(session.get::<_, String>("expired").await.unwrap().is_none());
+    assert!(backend.read_value("ttl-session-id", "expired").await.unwrap().is_none());
+    let accessed = backend.get_session_access("ttl-session-id").await.unwrap();
+    clock.advance(Duration::from_secs(2));
+    backend
+        .remove_values_if("ttl-session-id", |key, _| key == "collected")
+        .await
+        .unwrap();
+    assert_eq!(backend.get_session_access("ttl-session-id").await.unwrap(), accessed);
+    assert!(
+        backend
+            .read_value("ttl-session-id", "collected")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(backend.read_value("ttl-session-id", "key").await.unwrap().is_some());
+    backend.remove_session("ttl-session-id").await.unwrap();
+
+    let session = manager.get_session("flash-session-id");
+    session.flash("success", "Saved").await.unwrap();
+    session.flash("error", "Failed").await.unwrap();
+    assert_eq!(
+        session.take_flashes().await.unwrap(),
+        [Flash::new("success", "Saved"), Flash::new("error", "Failed")]
+    );
+    assert!(session.take_flashes().await.unwrap().is_empty());
+    backend.remove_session("flash-session-id").await.unwrap();
+
+    let session = manager.get_session("namespace-session-id");
+    let cart = session.namespace("cart").unwrap();
+    cart.set("id", &1).await.unwrap();
+    session.set("id", &2).await.unwrap();
+    session.set("cart:id", &3).await.unwrap();
+    assert_eq!(session.get::<_, u32>("__seance_ns.cart.id").await.unwrap(), Some(1));
+    cart.clear().await.unwrap();
+    assert!(cart.get::<_, u32>("id").await.unwrap().is_none());
+    assert_eq!(session.get::<_, u32>("id").await.unwrap(), Some(2));
+    assert_eq!(session.get::<_, u32>("cart:id").await.unwrap(), Some(3));
+    backend.remove_session("namespace-session-id").await.unwrap();
 }